@@ -1,8 +1,8 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use clap::Parser;
 use opencv::{core::UMat, highgui};
-use zbl::{Capturable, CaptureBuilder, Display, Window};
+use zbl::{util::FpsCounter, Capturable, CaptureBuilder, Display, Window};
 
 #[derive(Parser, Debug)]
 #[clap(version)]
@@ -14,7 +14,7 @@ struct Args {
 }
 
 fn main() {
-    zbl::init();
+    zbl::init().expect("failed to initialize zbl");
 
     let args = Args::parse();
 
@@ -41,35 +41,23 @@ fn main() {
     highgui::named_window("Test", highgui::WINDOW_NORMAL | highgui::WINDOW_KEEPRATIO)
         .expect("failed to setup opencv window");
 
-    let mut total_time = 0f32;
-    let mut total_seconds = 0;
-    let mut total_frames = 0;
-    let start = Instant::now();
+    let mut fps = FpsCounter::default();
+    let mut last_report = Instant::now();
     let mut gpu_mat = UMat::new_def();
     loop {
-        let t_frame_start = Instant::now();
         if let Some(mut frame) = capture.grab().expect("failed to get frame") {
             opencv::core::convert_from_d3d11_texture_2d(&mut frame.texture, &mut gpu_mat)
                 .expect("convert from d3d11 texture");
-            let t_frame_end = Instant::now();
 
             highgui::imshow("Test", &gpu_mat).expect("failed to show frame");
             if highgui::wait_key(8).expect("failed to wait user input") != -1 {
                 break;
             }
 
-            total_frames += 1;
-            total_time += (t_frame_end - t_frame_start).as_secs_f32();
-
-            let seconds_since_start = (Instant::now() - start).as_secs();
-            if seconds_since_start != total_seconds {
-                println!(
-                    "averaging {:.1} fps",
-                    1f32 / (total_time / total_frames as f32)
-                );
-                total_frames = 0;
-                total_time = 0f32;
-                total_seconds = seconds_since_start;
+            fps.tick();
+            if last_report.elapsed() >= Duration::from_secs(1) {
+                println!("averaging {:.1} fps", fps.fps());
+                last_report = Instant::now();
             }
         } else {
             break;
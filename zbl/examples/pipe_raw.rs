@@ -0,0 +1,86 @@
+//! Write captured frames to stdout as raw tightly packed BGRA, e.g.:
+//!
+//! ```text
+//! cargo run --example pipe_raw -- --display-id 0 | ffmpeg -f rawvideo -pix_fmt bgra -s WxH -i - out.mp4
+//! ```
+//!
+//! The frame size to pass to `ffmpeg` is printed to stderr once the first frame arrives.
+
+use std::{
+    io::{ErrorKind, Write},
+    os::raw::c_void,
+};
+
+use clap::Parser;
+use windows::Win32::Foundation::HWND;
+use zbl::{Capturable, CaptureBuilder, Display, Window};
+
+#[derive(Parser, Debug)]
+#[clap(version)]
+struct Args {
+    #[clap(long)]
+    window_name: Option<String>,
+    #[clap(long)]
+    window_handle: Option<isize>,
+    #[clap(long)]
+    display_id: Option<usize>,
+}
+
+fn main() {
+    zbl::init().expect("failed to initialize zbl");
+
+    let args = Args::parse();
+
+    let target = if let Some(window_name) = args.window_name {
+        let window = Window::find_first(&window_name).expect("failed to find window");
+        Box::new(window) as Box<dyn Capturable>
+    } else if let Some(window_handle) = args.window_handle {
+        let window = Window::new(HWND(window_handle as *mut c_void));
+        Box::new(window) as Box<dyn Capturable>
+    } else if let Some(display_id) = args.display_id {
+        let display = Display::find_by_id(display_id).expect("failed to find display");
+        Box::new(display) as Box<dyn Capturable>
+    } else {
+        panic!("either --window-name, --window-handle or --display-id should be set!");
+    };
+
+    let mut capture = CaptureBuilder::new(target)
+        .set_cpu_access(true)
+        .build_and_start()
+        .expect("failed to start capture");
+
+    let mut stdout = std::io::stdout().lock();
+    let mut size = None;
+    while let Some(frame) = capture.grab().expect("failed to get frame") {
+        let frame_size = (frame.width(), frame.height());
+        match size {
+            None => {
+                eprintln!("capturing {}x{} bgra", frame_size.0, frame_size.1);
+                size = Some(frame_size);
+            }
+            // raw video can't change its size mid-stream
+            Some(size) if size != frame_size => {
+                eprintln!(
+                    "frame size has changed to {}x{}, stopping",
+                    frame_size.0, frame_size.1
+                );
+                break;
+            }
+            _ => {}
+        }
+        match frame.write_packed(&mut stdout) {
+            Ok(()) => {}
+            // the reading end (e.g. ffmpeg) has exited
+            Err(zbl::Error::Io(e)) if e.kind() == ErrorKind::BrokenPipe => break,
+            Err(e) => panic!("failed to write frame: {}", e),
+        }
+        if let Err(e) = stdout.flush() {
+            if e.kind() == ErrorKind::BrokenPipe {
+                break;
+            }
+            panic!("failed to flush stdout: {}", e);
+        }
+    }
+
+    capture.stop().expect("failed to stop capture");
+}
@@ -1,4 +1,7 @@
-use std::{os::raw::c_void, time::Instant};
+use std::{
+    os::raw::c_void,
+    time::{Duration, Instant},
+};
 
 use clap::Parser;
 use opencv::{
@@ -6,7 +9,7 @@ use opencv::{
     highgui,
 };
 use windows::Win32::Foundation::HWND;
-use zbl::{capture::CaptureBuilder, Capturable, Display, Window};
+use zbl::{capture::CaptureBuilder, util::FpsCounter, Capturable, Display, Window};
 
 #[derive(Parser, Debug)]
 #[clap(version)]
@@ -20,7 +23,7 @@ struct Args {
 }
 
 fn main() {
-    zbl::init();
+    zbl::init().expect("failed to initialize zbl");
 
     let args = Args::parse();
 
@@ -40,50 +43,35 @@ fn main() {
     };
 
     let mut capture = CaptureBuilder::new(target)
-        .build()
-        .expect("failed to initialize capture");
-
-    capture.start().expect("failed to start capture");
+        .build_and_start()
+        .expect("failed to start capture");
 
     highgui::named_window("Test", highgui::WINDOW_NORMAL | highgui::WINDOW_KEEPRATIO)
         .expect("failed to setup opencv window");
 
-    let mut total_time = 0f32;
-    let mut total_seconds = 0;
-    let mut total_frames = 0;
-    let start = Instant::now();
+    let mut fps = FpsCounter::default();
+    let mut last_report = Instant::now();
     loop {
-        let t_frame_start = Instant::now();
         if let Some(frame) = capture.grab().expect("failed to get frame") {
-            let desc = frame.desc();
             let mat = unsafe {
                 Mat::new_size_with_data_unsafe(
-                    Size::new(desc.Width as i32, desc.Height as i32),
+                    Size::new(frame.width() as i32, frame.height() as i32),
                     CV_8UC4,
                     frame.mapped_ptr.pData,
-                    frame.mapped_ptr.RowPitch as usize,
+                    frame.row_pitch() as usize,
                 )
             }
             .expect("failed to convert to opencv frame");
-            let t_frame_end = Instant::now();
 
             highgui::imshow("Test", &mat).expect("failed to show frame");
             if highgui::wait_key(8).expect("failed to wait user input") != -1 {
                 break;
             }
 
-            total_frames += 1;
-            total_time += (t_frame_end - t_frame_start).as_secs_f32();
-
-            let seconds_since_start = (Instant::now() - start).as_secs();
-            if seconds_since_start != total_seconds {
-                println!(
-                    "averaging {} fps",
-                    1f32 / (total_time / total_frames as f32)
-                );
-                total_frames = 0;
-                total_time = 0f32;
-                total_seconds = seconds_since_start;
+            fps.tick();
+            if last_report.elapsed() >= Duration::from_secs(1) {
+                println!("averaging {:.1} fps", fps.fps());
+                last_report = Instant::now();
             }
         } else {
             break;
@@ -0,0 +1,181 @@
+//! Flat C API for using zbl from other languages (C, C++, C#, ...).
+//!
+//! Build it as a dynamic library with
+//! `cargo rustc -p zbl --release --features capi --crate-type cdylib`.
+//!
+//! Captures are opaque `ZblCapture*` handles created by `zbl_capture_create_for_window` /
+//! `zbl_capture_create_for_display` and freed by `zbl_capture_destroy`. Functions returning a
+//! status return `ZBL_OK` on success, and `zbl_last_error` describes the last failure on the
+//! calling thread.
+//!
+//! Ownership and lifetime rules:
+//! * a `ZblCapture*` must only be used from the thread that created it, and not after
+//!   `zbl_capture_destroy`;
+//! * `ZblFrame::data` is owned by the capture - it stays valid until the next
+//!   `zbl_capture_grab` call or until the capture is destroyed, whichever happens first, and must
+//!   not be freed by the caller. Copy the data if it has to outlive that;
+//! * the string returned by `zbl_last_error` is owned by zbl and stays valid until the next call
+//!   into zbl on the same thread.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CString},
+    ptr::null,
+};
+
+use windows::Win32::Foundation::HWND;
+
+use crate::{Capturable, Capture, CaptureBuilder, Display, Result, Window};
+
+/// The call succeeded.
+pub const ZBL_OK: i32 = 0;
+/// The capture has ended (e.g. the window was closed), no frame has been grabbed.
+pub const ZBL_CLOSED: i32 = 1;
+/// The call failed, see `zbl_last_error`.
+pub const ZBL_ERROR: i32 = -1;
+/// A null pointer was passed where a valid one is required.
+pub const ZBL_INVALID_ARGUMENT: i32 = -2;
+
+/// Opaque capture handle.
+pub struct ZblCapture {
+    inner: Capture,
+}
+
+/// A frame grabbed by `zbl_capture_grab`. See the module docs for the lifetime of `data`.
+#[repr(C)]
+pub struct ZblFrame {
+    /// Pixel data, `row_pitch * height` bytes.
+    pub data: *const c_void,
+    pub width: u32,
+    pub height: u32,
+    /// Number of bytes between the starts of two rows, which might be larger than
+    /// `width * bytes_per_pixel`.
+    pub row_pitch: u32,
+    pub bytes_per_pixel: u32,
+    /// `DXGI_FORMAT` of the pixel data, e.g. 87 for `DXGI_FORMAT_B8G8R8A8_UNORM`.
+    pub format: u32,
+    /// Time the frame was captured at, in 100ns units of `QueryPerformanceCounter` time.
+    pub timestamp: u64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // interior nul bytes can't be represented, which is unlikely for error messages
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+fn create(capturable: Result<Box<dyn Capturable>>) -> *mut ZblCapture {
+    let capture = capturable.and_then(|capturable| {
+        crate::init()?;
+        CaptureBuilder::new(capturable).set_cpu_access(true).build()
+    });
+    match capture {
+        Ok(inner) => Box::into_raw(Box::new(ZblCapture { inner })),
+        Err(e) => {
+            set_last_error(e.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn to_status(result: Result<()>) -> i32 {
+    match result {
+        Ok(()) => ZBL_OK,
+        Err(e) => {
+            set_last_error(e.to_string());
+            ZBL_ERROR
+        }
+    }
+}
+
+/// Get the message of the last error on the calling thread, or null if there was none.
+#[no_mangle]
+pub extern "C" fn zbl_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(null(), |message| message.as_ptr())
+    })
+}
+
+/// Create a capture of the window with the given handle. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn zbl_capture_create_for_window(window_handle: isize) -> *mut ZblCapture {
+    let window = Window::new(HWND(window_handle as *mut c_void));
+    create(Ok(Box::new(window)))
+}
+
+/// Create a capture of the display with the given index, in the order of `EnumDisplayMonitors`.
+/// Returns null on failure.
+#[no_mangle]
+pub extern "C" fn zbl_capture_create_for_display(index: u32) -> *mut ZblCapture {
+    create(
+        Display::find_by_id(index as usize).map(|display| Box::new(display) as Box<dyn Capturable>),
+    )
+}
+
+/// Start capturing frames.
+///
+/// # Safety
+///
+/// `capture` must be null or a handle returned by one of the `zbl_capture_create_*` functions.
+#[no_mangle]
+pub unsafe extern "C" fn zbl_capture_start(capture: *mut ZblCapture) -> i32 {
+    let Some(capture) = capture.as_mut() else {
+        return ZBL_INVALID_ARGUMENT;
+    };
+    to_status(capture.inner.start())
+}
+
+/// Block until the next frame arrives and fill `frame` with it. Returns `ZBL_CLOSED` if the
+/// capture has ended.
+///
+/// # Safety
+///
+/// `capture` must be null or a handle returned by one of the `zbl_capture_create_*` functions,
+/// and `frame` must be null or point to a writable `ZblFrame`.
+#[no_mangle]
+pub unsafe extern "C" fn zbl_capture_grab(capture: *mut ZblCapture, frame: *mut ZblFrame) -> i32 {
+    let (Some(capture), Some(out)) = (capture.as_mut(), frame.as_mut()) else {
+        return ZBL_INVALID_ARGUMENT;
+    };
+    match capture.inner.grab() {
+        Ok(Some(frame)) => {
+            let desc = frame.desc();
+            *out = ZblFrame {
+                data: frame.mapped_ptr.pData,
+                width: desc.Width,
+                height: desc.Height,
+                row_pitch: frame.row_pitch(),
+                bytes_per_pixel: frame.bytes_per_pixel(),
+                format: desc.Format.0 as u32,
+                timestamp: (frame.timestamp.as_nanos() / 100) as u64,
+            };
+            ZBL_OK
+        }
+        Ok(None) => ZBL_CLOSED,
+        Err(e) => {
+            set_last_error(e.to_string());
+            ZBL_ERROR
+        }
+    }
+}
+
+/// Stop capturing and free the capture. Frames grabbed from it become invalid. Passing null is
+/// a no-op.
+///
+/// # Safety
+///
+/// `capture` must be null or a handle returned by one of the `zbl_capture_create_*` functions,
+/// which has not been destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn zbl_capture_destroy(capture: *mut ZblCapture) {
+    if !capture.is_null() {
+        drop(Box::from_raw(capture));
+    }
+}
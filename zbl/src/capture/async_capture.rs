@@ -0,0 +1,91 @@
+use std::thread::JoinHandle;
+
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver};
+use windows::{core::Error, Win32::Foundation::E_FAIL};
+
+use crate::Result;
+
+use crate::frame::OwnedFrame;
+
+use super::{
+    channel::{pump_frames, CaptureHandle},
+    Capture,
+};
+
+/// A capture running on a dedicated thread, delivering frames through a `tokio` channel.
+///
+/// `Capture` holds COM objects and can't be sent between threads, so it is created and driven on
+/// the capture thread, and only copies of the frames (as `OwnedFrame`s) are sent to async code.
+/// Requires CPU access.
+pub struct AsyncCapture {
+    handle: CaptureHandle,
+    receiver: Receiver<OwnedFrame>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl AsyncCapture {
+    /// Spawn a capture thread. `make_capture` is called on that thread to create the `Capture`,
+    /// which is then started immediately.
+    pub fn spawn<F>(make_capture: F) -> Self
+    where
+        F: FnOnce() -> Result<Capture> + Send + 'static,
+    {
+        let (sender, receiver) = channel(1 << 5);
+        let handle = CaptureHandle::default();
+        let thread_handle = handle.clone();
+        let thread = std::thread::spawn(move || {
+            crate::init()?;
+            let mut capture = make_capture()?;
+            pump_frames(
+                &mut capture,
+                thread_handle.stop_flag(),
+                |frame| match sender.try_send(frame) {
+                    Err(TrySendError::Full(_)) => {
+                        log::info!("dropping frame, receiver is not keeping up");
+                        true
+                    }
+                    Err(TrySendError::Closed(_)) => false,
+                    Ok(()) => true,
+                },
+            )
+        });
+        Self {
+            handle,
+            receiver,
+            thread: Some(thread),
+        }
+    }
+
+    /// Receive the next frame. Returns `None` when the capture has ended.
+    pub async fn next_frame(&mut self) -> Option<OwnedFrame> {
+        self.receiver.recv().await
+    }
+
+    /// Get a handle which can be used to stop the capture from anywhere.
+    pub fn handle(&self) -> CaptureHandle {
+        self.handle.clone()
+    }
+
+    /// Signal the capture thread to stop. `next_frame()` will return `None` once it has exited.
+    pub fn stop(&self) {
+        self.handle.stop();
+    }
+
+    /// Stop the capture and wait for the capture thread to exit, returning the error it has
+    /// encountered, if any. This blocks the current thread.
+    pub fn join(mut self) -> Result<()> {
+        self.stop();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| Error::new(E_FAIL, "capture thread has panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AsyncCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
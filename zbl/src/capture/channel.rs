@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use windows::{core::Error, Win32::Foundation::E_INVALIDARG};
+
+use crate::Result;
+
+use crate::frame::OwnedFrame;
+
+use super::{Capture, MaybeFrame};
+
+/// How often the capture is polled if the refresh rate of its target is unknown.
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// A handle to a `Capture` driven by a `FramePump` (see `Capture::into_channel()`) or by a
+/// dedicated capture thread.
+///
+/// Unlike `Capture`, this handle can be sent to other threads.
+#[derive(Clone, Default)]
+pub struct CaptureHandle {
+    stop_requested: Arc<AtomicBool>,
+}
+
+impl CaptureHandle {
+    pub(crate) fn stop_flag(&self) -> &AtomicBool {
+        &self.stop_requested
+    }
+
+    /// Request the capture to stop. The `FramePump` will stop the capture and return from `run()`.
+    pub fn stop(&self) {
+        self.stop_requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stop_requested(&self) -> bool {
+        self.stop_requested.load(Ordering::SeqCst)
+    }
+}
+
+/// Drives a `Capture` on the thread owning it, copying frames to CPU memory and sending them to
+/// the channel returned by `Capture::into_channel()`.
+pub struct FramePump {
+    capture: Capture,
+    sender: SyncSender<OwnedFrame>,
+    handle: CaptureHandle,
+}
+
+impl FramePump {
+    /// Start the capture and deliver frames until the capture ends, the receiver is dropped, or
+    /// `CaptureHandle::stop()` is called. This blocks the current thread.
+    pub fn run(mut self) -> Result<()> {
+        let sender = self.sender;
+        pump_frames(
+            &mut self.capture,
+            self.handle.stop_flag(),
+            |frame| match sender.try_send(frame) {
+                Err(TrySendError::Full(_)) => {
+                    log::info!("dropping frame, receiver is not keeping up");
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+                Ok(()) => true,
+            },
+        )
+    }
+}
+
+/// Start the capture and pass copies of its frames to `send` until the capture ends, `send`
+/// returns `false`, or stop is requested. While there are no new frames, the capture is polled
+/// at its `frame_interval_hint()`.
+pub(crate) fn pump_frames(
+    capture: &mut Capture,
+    stop_requested: &AtomicBool,
+    mut send: impl FnMut(OwnedFrame) -> bool,
+) -> Result<()> {
+    if !capture.has_cpu_access() {
+        return Err(Error::new(
+            E_INVALIDARG,
+            "delivering frames over a channel requires a capture with CPU access",
+        )
+        .into());
+    }
+    capture.start()?;
+    let poll_interval = capture.frame_interval_hint().unwrap_or(POLL_INTERVAL);
+    while !stop_requested.load(Ordering::SeqCst) {
+        match capture.try_grab()? {
+            MaybeFrame::Some(frame) => {
+                if !send(frame.to_owned()?) {
+                    break;
+                }
+            }
+            MaybeFrame::Pending | MaybeFrame::Minimized => std::thread::sleep(poll_interval),
+            MaybeFrame::None => return Ok(()),
+        }
+    }
+    capture.stop()
+}
+
+pub(crate) fn into_channel(
+    capture: Capture,
+    capacity: usize,
+) -> (CaptureHandle, Receiver<OwnedFrame>, FramePump) {
+    let (sender, receiver) = sync_channel(capacity);
+    let handle = CaptureHandle::default();
+    let pump = FramePump {
+        capture,
+        sender,
+        handle: handle.clone(),
+    };
+    (handle, receiver, pump)
+}
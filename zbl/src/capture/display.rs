@@ -1,32 +1,46 @@
 use std::{
-    collections::HashMap,
     ptr::null_mut,
     sync::{
         mpsc::{sync_channel, Receiver, SyncSender},
-        RwLock,
+        Mutex,
     },
 };
 
-use once_cell::sync::Lazy;
 use windows::{
-    core::{factory, Result},
+    core::{Error, Interface, PCWSTR},
     Graphics::Capture::GraphicsCaptureItem,
     Win32::{
-        Foundation::{BOOL, LPARAM, RECT},
+        Devices::Display::{
+            DisplayConfigGetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig,
+            DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+            DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_HEADER,
+            DISPLAYCONFIG_DEVICE_INFO_TYPE, DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO,
+            DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_SDR_WHITE_LEVEL,
+            DISPLAYCONFIG_SOURCE_DEVICE_NAME, QDC_ONLY_ACTIVE_PATHS,
+        },
+        Foundation::{BOOL, E_INVALIDARG, LPARAM, RECT},
         Graphics::{
             Direct3D11::D3D11_BOX,
-            Gdi::{EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFOEXW},
+            Dxgi::{CreateDXGIFactory1, IDXGIFactory1, IDXGIOutput6},
+            Gdi::{
+                EnumDisplayDevicesW, EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW,
+                MonitorFromWindow, DEVMODEW, DISPLAY_DEVICEW, ENUM_CURRENT_SETTINGS, HDC, HMONITOR,
+                MONITORINFOEXW, MONITOR_DEFAULTTONEAREST,
+            },
+        },
+        UI::{
+            HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI},
+            WindowsAndMessaging::MONITORINFOF_PRIMARY,
         },
-        System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
     },
 };
 
-use crate::util::convert_u16_string;
+use crate::Result;
 
-use super::Capturable;
+use crate::util::convert_u16_string;
 
-static OBJECT_DESTROYED_USER_DATA: Lazy<RwLock<HashMap<isize, (isize, SyncSender<()>)>>> =
-    Lazy::new(Default::default);
+use super::{capture_item_interop, window::Window, Capturable};
 
 fn get_monitor_info(handle: HMONITOR) -> Result<MONITORINFOEXW> {
     let mut info = MONITORINFOEXW::default();
@@ -37,6 +51,85 @@ fn get_monitor_info(handle: HMONITOR) -> Result<MONITORINFOEXW> {
     Ok(info)
 }
 
+fn get_monitor_friendly_name(device_name: &[u16]) -> Option<String> {
+    let mut device = DISPLAY_DEVICEW {
+        cb: std::mem::size_of::<DISPLAY_DEVICEW>() as u32,
+        ..Default::default()
+    };
+    let found =
+        unsafe { EnumDisplayDevicesW(PCWSTR(device_name.as_ptr()), 0, &mut device, 0) }.as_bool();
+    found.then(|| convert_u16_string(&device.DeviceString))
+}
+
+/// Find the active display config path whose source is the display with the given device name.
+fn find_display_config_path(device_name: &[u16]) -> Option<DISPLAYCONFIG_PATH_INFO> {
+    let (mut path_count, mut mode_count) = (0, 0);
+    unsafe { GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count) }
+        .ok()
+        .ok()?;
+    let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+    let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+    unsafe {
+        QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut path_count,
+            paths.as_mut_ptr(),
+            &mut mode_count,
+            modes.as_mut_ptr(),
+            None,
+        )
+    }
+    .ok()
+    .ok()?;
+    paths.truncate(path_count as usize);
+
+    let device_name = convert_u16_string(device_name);
+    paths.into_iter().find(|path| {
+        let mut source_name = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+            header: display_config_header::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>(
+                DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+                path.sourceInfo.adapterId,
+                path.sourceInfo.id,
+            ),
+            ..Default::default()
+        };
+        let found = unsafe { DisplayConfigGetDeviceInfo(&mut source_name.header) } == 0;
+        found && convert_u16_string(&source_name.viewGdiDeviceName) == device_name
+    })
+}
+
+fn display_config_header<T>(
+    r#type: DISPLAYCONFIG_DEVICE_INFO_TYPE,
+    adapter_id: windows::Win32::Foundation::LUID,
+    id: u32,
+) -> DISPLAYCONFIG_DEVICE_INFO_HEADER {
+    DISPLAYCONFIG_DEVICE_INFO_HEADER {
+        r#type,
+        size: std::mem::size_of::<T>() as u32,
+        adapterId: adapter_id,
+        id,
+    }
+}
+
+/// Get the peak luminance of the DXGI output showing the given monitor, in nits.
+fn get_output_max_luminance(handle: HMONITOR) -> Option<f32> {
+    let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1() }.ok()?;
+    let mut adapter_index = 0;
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(adapter_index) } {
+        let mut output_index = 0;
+        while let Ok(output) = unsafe { adapter.EnumOutputs(output_index) } {
+            if unsafe { output.GetDesc() }.is_ok_and(|desc| desc.Monitor == handle) {
+                let desc = unsafe { output.cast::<IDXGIOutput6>().ok()?.GetDesc1() }.ok()?;
+                // 0 means the display didn't report it
+                return (desc.MaxLuminance > 0.0).then_some(desc.MaxLuminance);
+            }
+            output_index += 1;
+        }
+        adapter_index += 1;
+    }
+    None
+}
+
 extern "system" fn enum_monitor(monitor: HMONITOR, _: HDC, _: *mut RECT, state: LPARAM) -> BOOL {
     unsafe {
         let state = Box::leak(Box::from_raw(state.0 as *mut Vec<Result<Display>>));
@@ -59,11 +152,37 @@ fn enumerate_displays() -> Result<Box<Vec<Result<Display>>>> {
     }
 }
 
-#[derive(Clone, Debug)]
+/// HDR metadata of a display, see `Display::hdr_info()`.
+#[derive(Clone, Copy, Debug)]
+pub struct HdrInfo {
+    /// Whether HDR (advanced color) is currently enabled on the display.
+    pub hdr_enabled: bool,
+    /// Luminance SDR white is displayed at in HDR mode, in nits. In `R16G16B16A16Float` frames
+    /// (scRGB) 1.0 corresponds to 80 nits, so SDR white has the value `sdr_white_level / 80.0`.
+    pub sdr_white_level: f32,
+    /// Peak luminance of the display in nits, if reported by the display.
+    pub max_luminance: Option<f32>,
+}
+
+#[derive(Debug)]
 pub struct Display {
     pub handle: HMONITOR,
     pub display_name: String,
     pub display_info: MONITORINFOEXW,
+    // displays never close, so senders are only kept alive to prevent receivers handed out to
+    // `Capture` from disconnecting
+    close_senders: Mutex<Vec<SyncSender<()>>>,
+}
+
+impl Clone for Display {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle,
+            display_name: self.display_name.clone(),
+            display_info: self.display_info,
+            close_senders: Default::default(),
+        }
+    }
 }
 
 impl Display {
@@ -74,14 +193,154 @@ impl Display {
             handle,
             display_name,
             display_info,
+            close_senders: Default::default(),
         })
     }
 
+    /// Get all displays, in the order they are enumerated by `EnumDisplayMonitors`.
+    pub fn enumerate() -> Result<Vec<Display>> {
+        enumerate_displays()?.into_iter().collect()
+    }
+
     pub fn find_by_id(id: usize) -> Result<Self> {
-        let displays = *enumerate_displays()?;
-        displays[id].clone()
+        let mut displays = *enumerate_displays()?;
+        check_display_index(id, displays.len())?;
+        displays.swap_remove(id)
+    }
+
+    /// Find the display the given window is on. A window spanning several displays resolves to
+    /// the one it has the largest intersection with, and a window which is not on any display
+    /// (e.g. minimized or moved off-screen) resolves to the nearest one.
+    pub fn from_window(window: &Window) -> Result<Self> {
+        let handle = unsafe { MonitorFromWindow(window.handle, MONITOR_DEFAULTTONEAREST) };
+        if handle.is_invalid() {
+            return Err(Error::new(E_INVALIDARG, "window is not on any display").into());
+        }
+        Self::new(handle)
+    }
+
+    /// Find the primary display.
+    pub fn primary() -> Result<Self> {
+        for display in enumerate_displays()?.into_iter() {
+            let display = display?;
+            if display.is_primary() {
+                return Ok(display);
+            }
+        }
+        Err(Error::new(E_INVALIDARG, "primary display not found").into())
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.display_info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0
+    }
+
+    /// Find a display by its device name (e.g. `\\.\DISPLAY1`) or by the friendly name of the
+    /// monitor connected to it.
+    pub fn find_by_name(name: &str) -> Result<Self> {
+        let displays = enumerate_displays()?
+            .into_iter()
+            .collect::<Result<Vec<Display>>>()?;
+        if let Some(display) = displays.iter().find(|display| {
+            display.display_name.eq_ignore_ascii_case(name)
+                || display.get_friendly_name().is_some_and(|n| n == name)
+        }) {
+            return Ok(display.clone());
+        }
+        let available = displays
+            .iter()
+            .map(|display| match display.get_friendly_name() {
+                Some(friendly_name) => format!("{} ({})", display.display_name, friendly_name),
+                None => display.display_name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        Err(Error::new(
+            E_INVALIDARG,
+            format!(
+                "display '{}' not found, available displays: {}",
+                name, available
+            ),
+        )
+        .into())
+    }
+
+    /// Get the friendly name of the monitor connected to this display, if available.
+    pub fn get_friendly_name(&self) -> Option<String> {
+        get_monitor_friendly_name(&self.display_info.szDevice)
+    }
+
+    /// Get the current refresh rate of this display in Hz.
+    ///
+    /// This is best-effort: `None` is returned if the driver reports the hardware default, and
+    /// virtual or remote displays might report an arbitrary value (commonly 60).
+    pub fn refresh_rate(&self) -> Option<u32> {
+        let mut mode = DEVMODEW {
+            dmSize: std::mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+        let found = unsafe {
+            EnumDisplaySettingsW(
+                PCWSTR(self.display_info.szDevice.as_ptr()),
+                ENUM_CURRENT_SETTINGS,
+                &mut mode,
+            )
+        }
+        .as_bool();
+        // 0 and 1 both mean "hardware default"
+        (found && mode.dmDisplayFrequency > 1).then_some(mode.dmDisplayFrequency)
+    }
+
+    /// Get the HDR state and white levels of this display, e.g. to tone-map frames captured with
+    /// the `R16G16B16A16Float` pixel format.
+    ///
+    /// Returns `None` if this information is not available, e.g. on systems older than
+    /// Windows 10 1709.
+    pub fn hdr_info(&self) -> Option<HdrInfo> {
+        let path = find_display_config_path(&self.display_info.szDevice)?;
+        let (adapter_id, id) = (path.targetInfo.adapterId, path.targetInfo.id);
+
+        let mut white_level = DISPLAYCONFIG_SDR_WHITE_LEVEL {
+            header: display_config_header::<DISPLAYCONFIG_SDR_WHITE_LEVEL>(
+                DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+                adapter_id,
+                id,
+            ),
+            ..Default::default()
+        };
+        if unsafe { DisplayConfigGetDeviceInfo(&mut white_level.header) } != 0 {
+            return None;
+        }
+        let mut color_info = DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO {
+            header: display_config_header::<DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO>(
+                DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+                adapter_id,
+                id,
+            ),
+            ..Default::default()
+        };
+        if unsafe { DisplayConfigGetDeviceInfo(&mut color_info.header) } != 0 {
+            return None;
+        }
+
+        Some(HdrInfo {
+            // bit 1 is `advancedColorEnabled`
+            hdr_enabled: unsafe { color_info.Anonymous.value } & 0b10 != 0,
+            // reported in multiples of 80 nits / 1000
+            sdr_white_level: white_level.SDRWhiteLevel as f32 * 80.0 / 1000.0,
+            max_luminance: get_output_max_luminance(self.handle),
+        })
+    }
+
+    /// Get the effective (x, y) DPI of this display, which accounts for the scale factor set by
+    /// the user. 96 DPI corresponds to 100% scale.
+    pub fn dpi(&self) -> Result<(u32, u32)> {
+        let (mut x, mut y) = (0, 0);
+        unsafe { GetDpiForMonitor(self.handle, MDT_EFFECTIVE_DPI, &mut x, &mut y)? };
+        Ok((x, y))
     }
 
+    /// Get the size of this display in virtual screen coordinates. Only the extent of `rcMonitor`
+    /// is used, its origin (which is non-zero for secondary monitors) does not matter here.
     pub fn get_virtual_size(&self) -> (i32, i32) {
         let rect = self.display_info.monitorInfo.rcMonitor;
         (rect.right - rect.left, rect.bottom - rect.top)
@@ -90,32 +349,108 @@ impl Display {
 
 impl Capturable for Display {
     fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
-        let interop = factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
-        unsafe { interop.CreateForMonitor(self.handle) }
+        let interop = capture_item_interop()?;
+        Ok(unsafe { interop.CreateForMonitor(self.handle)? })
     }
 
     fn get_client_box(&self) -> Result<D3D11_BOX> {
-        let (w, h) = self.get_virtual_size();
-        Ok(D3D11_BOX {
-            left: 0,
-            right: w as u32,
-            top: 0,
-            bottom: h as u32,
-            front: 0,
-            back: 1,
-        })
+        Ok(monitor_client_box(&self.display_info.monitorInfo.rcMonitor))
     }
 
     fn get_close_notification_channel(&self) -> Receiver<()> {
         let (sender, receiver) = sync_channel(1);
-        OBJECT_DESTROYED_USER_DATA
-            .write()
-            .unwrap()
-            .insert(self.handle.0 as isize, (self.handle.0 as isize, sender));
+        if let Ok(mut senders) = self.close_senders.lock() {
+            senders.push(sender);
+        }
         receiver
     }
 
+    fn release_close_notification_channels(&self) {
+        if let Ok(mut senders) = self.close_senders.lock() {
+            senders.clear();
+        }
+    }
+
     fn get_raw_handle(&self) -> isize {
         self.handle.0 as isize
     }
+
+    fn get_screen_origin(&self) -> Option<(i32, i32)> {
+        let rect = self.display_info.monitorInfo.rcMonitor;
+        Some((rect.left, rect.top))
+    }
+
+    fn get_refresh_rate(&self) -> Option<u32> {
+        Display::refresh_rate(self)
+    }
+}
+
+/// Get the client box of a monitor with the given bounds in virtual screen coordinates.
+fn monitor_client_box(monitor: &RECT) -> D3D11_BOX {
+    // frames produced for a monitor item always start at the monitor's own top-left corner,
+    // regardless of where it is positioned on the virtual screen (e.g. at negative coordinates
+    // to the left of the primary monitor), so no offset by `monitor.left/top` is needed
+    D3D11_BOX {
+        left: 0,
+        right: (monitor.right - monitor.left).max(0) as u32,
+        top: 0,
+        bottom: (monitor.bottom - monitor.top).max(0) as u32,
+        front: 0,
+        back: 1,
+    }
+}
+
+/// Check that `index` refers to one of `count` enumerated displays.
+fn check_display_index(index: usize, count: usize) -> Result<()> {
+    if index >= count {
+        return Err(crate::Error::DisplayIndexOutOfRange { index, count });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_box(left: i32, top: i32, right: i32, bottom: i32) -> D3D11_BOX {
+        monitor_client_box(&RECT {
+            left,
+            top,
+            right,
+            bottom,
+        })
+    }
+
+    #[test]
+    fn client_box_of_primary_monitor() {
+        let client_box = client_box(0, 0, 1920, 1080);
+        assert_eq!((client_box.left, client_box.top), (0, 0));
+        assert_eq!((client_box.right, client_box.bottom), (1920, 1080));
+    }
+
+    #[test]
+    fn client_box_of_monitor_at_negative_coordinates() {
+        // secondary monitor to the left of and above the primary one
+        let client_box = client_box(-2560, -360, 0, 1080);
+        assert_eq!((client_box.left, client_box.top), (0, 0));
+        assert_eq!((client_box.right, client_box.bottom), (2560, 1440));
+    }
+
+    #[test]
+    fn display_index_in_range() {
+        assert!(check_display_index(0, 1).is_ok());
+        assert!(check_display_index(2, 3).is_ok());
+    }
+
+    #[test]
+    fn display_index_out_of_range() {
+        assert!(matches!(
+            check_display_index(3, 3),
+            Err(crate::Error::DisplayIndexOutOfRange { index: 3, count: 3 })
+        ));
+        assert!(matches!(
+            check_display_index(0, 0),
+            Err(crate::Error::DisplayIndexOutOfRange { index: 0, count: 0 })
+        ));
+    }
 }
@@ -0,0 +1,86 @@
+use std::sync::{
+    mpsc::{sync_channel, Receiver, SyncSender},
+    Mutex,
+};
+
+use windows::{
+    core::Interface,
+    Graphics::Capture::{GraphicsCaptureItem, GraphicsCapturePicker},
+    Win32::{Foundation::HWND, Graphics::Direct3D11::D3D11_BOX, UI::Shell::IInitializeWithWindow},
+};
+
+use crate::Result;
+
+use super::Capturable;
+
+/// A `Capturable` wrapping an already existing `GraphicsCaptureItem`, e.g. one obtained from
+/// `GraphicsCapturePicker`.
+pub struct RawCaptureItem {
+    pub item: GraphicsCaptureItem,
+    // senders are kept alive so that receivers handed out to `Capture` never disconnect
+    close_senders: Mutex<Vec<SyncSender<()>>>,
+}
+
+impl RawCaptureItem {
+    pub fn new(item: GraphicsCaptureItem) -> Self {
+        Self {
+            item,
+            close_senders: Default::default(),
+        }
+    }
+}
+
+/// Let the user choose a window or a display to capture using the system `GraphicsCapturePicker`.
+///
+/// Desktop applications have to associate the picker with a window, so `owner` should be a valid
+/// window handle belonging to a thread that pumps messages. Note that this call blocks until the
+/// picker is closed, so it should not be made from that same UI thread.
+///
+/// Returns `Ok(None)` if the user has cancelled the selection.
+pub fn pick_capture_item(owner: HWND) -> Result<Option<GraphicsCaptureItem>> {
+    let picker = GraphicsCapturePicker::new()?;
+    let initialize_with_window: IInitializeWithWindow = picker.cast()?;
+    unsafe { initialize_with_window.Initialize(owner)? };
+    match picker.PickSingleItemAsync()?.get() {
+        Ok(item) => Ok(Some(item)),
+        // the operation completes with a null item when the picker is cancelled
+        Err(e) if e.code().is_ok() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Capturable for RawCaptureItem {
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
+        Ok(self.item.clone())
+    }
+
+    fn get_client_box(&self) -> Result<D3D11_BOX> {
+        let size = self.item.Size()?;
+        Ok(D3D11_BOX {
+            left: 0,
+            right: size.Width as u32,
+            top: 0,
+            bottom: size.Height as u32,
+            front: 0,
+            back: 1,
+        })
+    }
+
+    fn get_close_notification_channel(&self) -> Receiver<()> {
+        let (sender, receiver) = sync_channel(1);
+        if let Ok(mut senders) = self.close_senders.lock() {
+            senders.push(sender);
+        }
+        receiver
+    }
+
+    fn release_close_notification_channels(&self) {
+        if let Ok(mut senders) = self.close_senders.lock() {
+            senders.clear();
+        }
+    }
+
+    fn get_raw_handle(&self) -> isize {
+        0
+    }
+}
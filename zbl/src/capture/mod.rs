@@ -1,26 +1,64 @@
+#[cfg(feature = "tokio")]
+pub mod async_capture;
+pub mod channel;
 pub mod display;
+pub mod item;
+pub mod virtual_desktop;
 pub mod window;
 
-use std::sync::mpsc::{sync_channel, Receiver, TryRecvError, TrySendError};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, TryRecvError, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use windows::{
-    core::{IInspectable, Interface, Result},
-    Foundation::TypedEventHandler,
+    core::{factory, IInspectable, Interface, HSTRING},
+    Foundation::{Metadata::ApiInformation, TimeSpan, TypedEventHandler},
     Graphics::{
         Capture::{
-            Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem,
-            GraphicsCaptureSession,
+            Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureDirtyRegionMode,
+            GraphicsCaptureItem, GraphicsCaptureSession,
         },
         DirectX::DirectXPixelFormat,
-        SizeInt32,
+        RectInt32, SizeInt32,
     },
     Win32::{
-        Graphics::Direct3D11::{ID3D11Texture2D, D3D11_BOX, D3D11_TEXTURE2D_DESC},
-        System::WinRT::Direct3D11::IDirect3DDxgiInterfaceAccess,
+        Foundation::RECT,
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BOX, D3D11_TEXTURE2D_DESC,
+            },
+            Dxgi::{
+                Common::DXGI_FORMAT_NV12, DXGI_ERROR_DEVICE_HUNG, DXGI_ERROR_DEVICE_REMOVED,
+                DXGI_ERROR_DEVICE_RESET,
+            },
+        },
+        System::WinRT::{
+            Direct3D11::IDirect3DDxgiInterfaceAccess,
+            Graphics::Capture::IGraphicsCaptureItemInterop,
+        },
+        UI::WindowsAndMessaging::{GetCursorInfo, CURSORINFO, CURSOR_SHOWING},
     },
 };
 
-use crate::{d3d::D3D, frame::Frame};
+use crate::{Error, Result};
+
+use crate::{
+    d3d::{Scaler, TextureSharing, D3D},
+    frame::{CursorInfo, Frame, OwnedFrame},
+};
+
+use self::channel::{CaptureHandle, FramePump};
+
+/// How often `Capture` checks whether the capture target is closed while waiting for a frame,
+/// see `CaptureBuilder::set_close_poll_interval`.
+const DEFAULT_CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often `wait_for_first_frame` polls when the frame interval of the target is unknown.
+const FIRST_FRAME_POLL_INTERVAL: Duration = Duration::from_millis(16);
 
 pub trait Capturable {
     fn create_capture_item(&self) -> Result<GraphicsCaptureItem>;
@@ -29,14 +67,82 @@ pub trait Capturable {
 
     fn get_close_notification_channel(&self) -> Receiver<()>;
 
+    /// Release resources (e.g. event hooks) associated with the channels returned by
+    /// `get_close_notification_channel` of this instance. Called by `Capture` when it is stopped
+    /// or dropped.
+    fn release_close_notification_channels(&self) {}
+
+    /// Check whether the capture target is gone. This is polled periodically by `Capture` as a
+    /// fallback for cases when the close notification is not delivered.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
     fn get_raw_handle(&self) -> isize;
+
+    /// Whether the capture target is minimized. Windows.Graphics.Capture does not produce frames
+    /// for minimized windows. Displays are never minimized.
+    fn is_minimized(&self) -> bool {
+        false
+    }
+
+    /// Whether the capture target is currently able to produce frames.
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Update any cached information about the capture target. This is called by `Capture` when
+    /// the frame pool is recreated.
+    fn refresh(&mut self) {}
+
+    /// Position of the top-left corner of the captured texture in screen coordinates, if known.
+    fn get_screen_origin(&self) -> Option<(i32, i32)> {
+        None
+    }
+
+    /// Refresh rate in Hz of the display showing the capture target, if known.
+    fn get_refresh_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Ask the capture target to repaint itself, see `Capture::force_redraw`. Does nothing by
+    /// default.
+    fn request_redraw(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
+/// Callback receiving frames as soon as they arrive, see `CaptureBuilder::on_frame`.
+pub type FrameCallback = Box<dyn FnMut(&Frame) + Send>;
+
+/// Callback notified when the capture ends, see `CaptureBuilder::set_on_close`.
+pub type CloseCallback = Box<dyn FnOnce() + Send>;
+
 pub struct CaptureBuilder {
     capturable: Box<dyn Capturable>,
     is_cursor_capture_enabled: bool,
     is_border_required: bool,
+    allow_border_change_failure: bool,
     cpu_access: bool,
+    client_box_inset: ClientBoxInset,
+    capture_box: Option<D3D11_BOX>,
+    client_crop: Option<RECT>,
+    on_frame: Option<FrameCallback>,
+    on_close: Option<CloseCallback>,
+    max_fps: Option<f32>,
+    texture_sharing: TextureSharing,
+    dirty_region_only: bool,
+    output_size: Option<(u32, u32)>,
+    frame_policy: FramePolicy,
+    pixel_format: DirectXPixelFormat,
+    output_format: OutputFormat,
+    include_secondary_windows: bool,
+    min_update_interval: Option<Duration>,
+    warmup_frames: u32,
+    secondary_thread: bool,
+    warp: bool,
+    capture_cursor_metadata: bool,
+    close_poll_interval: Duration,
 }
 
 impl CaptureBuilder {
@@ -45,7 +151,27 @@ impl CaptureBuilder {
             capturable,
             is_cursor_capture_enabled: false,
             is_border_required: true,
+            allow_border_change_failure: true,
             cpu_access: true,
+            client_box_inset: Default::default(),
+            capture_box: None,
+            client_crop: None,
+            on_frame: None,
+            on_close: None,
+            max_fps: None,
+            texture_sharing: TextureSharing::Shared,
+            dirty_region_only: false,
+            output_size: None,
+            frame_policy: FramePolicy::Buffered,
+            pixel_format: DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            output_format: OutputFormat::Native,
+            include_secondary_windows: false,
+            min_update_interval: None,
+            warmup_frames: 0,
+            secondary_thread: true,
+            warp: false,
+            capture_cursor_metadata: false,
+            close_poll_interval: DEFAULT_CLOSE_POLL_INTERVAL,
         }
     }
 
@@ -54,39 +180,408 @@ impl CaptureBuilder {
         self
     }
 
+    /// Whether the yellow capture border should be drawn around the capture target.
+    ///
+    /// Disabling the border is not supported on older systems, in which case a warning is logged
+    /// and `Capture::border_change_failed()` returns `true`. For windows this is harmless, as the
+    /// border is drawn outside of the client area, but for displays it is drawn inside the captured
+    /// area - use `set_client_box_inset` to crop it if needed.
     pub fn set_is_border_required(mut self, val: bool) -> Self {
         self.is_border_required = val;
         self
     }
 
+    /// Whether to continue without an error when disabling the capture border fails (the
+    /// default). If set to `false`, building the capture fails instead when the border can't be
+    /// disabled with `set_is_border_required(false)` - use `session_capabilities().border_toggle` to
+    /// check for support beforehand.
+    pub fn set_allow_border_change_failure(mut self, val: bool) -> Self {
+        self.allow_border_change_failure = val;
+        self
+    }
+
     pub fn set_cpu_access(mut self, val: bool) -> Self {
         self.cpu_access = val;
         self
     }
 
+    /// Shrink the captured area by the given number of pixels from each side. This can be used
+    /// to compensate for residual borders which are present on some DWM configurations.
+    pub fn set_client_box_inset(mut self, left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        self.client_box_inset = ClientBoxInset {
+            left,
+            top,
+            right,
+            bottom,
+        };
+        self
+    }
+
+    /// Capture exactly this region of the capture item instead of the client area detected by
+    /// `Capturable::get_client_box()`. The client box inset is not applied to it.
+    ///
+    /// Building the capture fails if the box does not fit into the capture item. If the item
+    /// shrinks later on, the box is clamped to its new size.
+    pub fn set_capture_box(mut self, val: Option<D3D11_BOX>) -> Self {
+        self.capture_box = val;
+        self
+    }
+
+    /// Capture only this region of the client area, e.g. a toolbar of a window. The rect is in
+    /// client coordinates, i.e. relative to the top-left corner of `Capturable::get_client_box()`,
+    /// and is translated into the capture box. The client box inset is not applied to it, and it
+    /// is ignored if `set_capture_box` is used.
+    ///
+    /// Building the capture fails if the rect is empty or does not fit into the client area. If
+    /// the client area shrinks later on, the crop is clamped to its new size.
+    pub fn set_client_crop(mut self, val: Option<RECT>) -> Self {
+        self.client_crop = val;
+        self
+    }
+
+    /// Set a callback to be invoked for every frame as soon as it arrives.
+    ///
+    /// The callback runs on the Windows.Graphics.Capture thread pool thread and must be fast -
+    /// WGC keeps only one frame in its pool, so frames are dropped while the callback is running.
+    /// The frame passed to it is the raw texture produced by WGC, which is not cropped to the
+    /// client area, and is not CPU-accessible.
+    ///
+    /// When this is set, frames are delivered only to the callback, and `grab()` will not return
+    /// any frames.
+    pub fn on_frame(mut self, callback: FrameCallback) -> Self {
+        self.on_frame = Some(callback);
+        self
+    }
+
+    /// Set a callback to be invoked once when the capture ends for any reason other than calling
+    /// `Capture::stop()`, e.g. because the target window has been closed. Use
+    /// `Capture::close_reason()` to find out why.
+    ///
+    /// The end of the capture is detected while waiting for frames, so the callback is called
+    /// from the thread calling `grab()`/`try_grab()`, not from a background thread. It is not
+    /// called again if the capture ends after a `restart()`.
+    pub fn set_on_close(mut self, callback: CloseCallback) -> Self {
+        self.on_close = Some(callback);
+        self
+    }
+
+    /// Limit the rate at which frames are delivered. Frames arriving sooner than `1 / max_fps`
+    /// seconds after the previously delivered frame are dropped as soon as they arrive.
+    ///
+    /// The actual frame rate will be at most `max_fps`, and at most the refresh rate of the display.
+    /// `build()` fails if `max_fps` is not a positive finite number.
+    pub fn set_max_fps(mut self, val: Option<f32>) -> Self {
+        self.max_fps = val;
+        self
+    }
+
+    /// Create the staging texture with `D3D11_RESOURCE_MISC_SHARED_NTHANDLE`, so that frames can
+    /// be shared with other devices or processes via `Frame::shared_handle()`. Only has effect
+    /// for captures without CPU access.
+    ///
+    /// Note that some consumers don't accept textures with this flag set - for example, OpenCV's
+    /// OpenCL interop (`cv::directx::convertFromD3D11Texture2D`) fails on such textures.
+    pub fn set_shared(mut self, val: bool) -> Self {
+        self.texture_sharing = if val {
+            TextureSharing::SharedNtHandle
+        } else {
+            TextureSharing::Shared
+        };
+        self
+    }
+
+    /// Set the sharing flags of the staging texture. Only has effect for captures without CPU
+    /// access. Defaults to `TextureSharing::Shared`.
+    ///
+    /// Use `TextureSharing::None` for consumers which reject shared textures, e.g. OpenCV's
+    /// OpenCL interop on some drivers.
+    pub fn set_texture_sharing(mut self, val: TextureSharing) -> Self {
+        self.texture_sharing = val;
+        self
+    }
+
+    /// Copy only the changed region of each frame into the staging texture, and report it in
+    /// `Frame::dirty_rect`. The rest of the staging texture keeps the contents of the previous
+    /// frames, so frames are still complete.
+    ///
+    /// This requires dirty region support in Windows.Graphics.Capture, which is available starting
+    /// with Windows 11 24H2 (build 26100). On older systems, this falls back to copying full frames,
+    /// and `Frame::dirty_rect` is always `None`.
+    pub fn set_dirty_region_only(mut self, val: bool) -> Self {
+        self.dirty_region_only = val;
+        self
+    }
+
+    /// Scale frames down to the given size on the GPU, before they are copied to CPU memory. This
+    /// only has effect when the captured content is larger than the output size in any dimension.
+    ///
+    /// The aspect ratio is not preserved automatically - compute the output size from
+    /// `Capture::content_size()` if needed. Dirty regions are not reported for scaled frames.
+    pub fn set_output_size(mut self, width: u32, height: u32) -> Self {
+        self.output_size = Some((width, height));
+        self
+    }
+
+    /// Set how frames that have arrived but haven't been grabbed yet are handled. Defaults to
+    /// `FramePolicy::Buffered`.
+    pub fn set_frame_policy(mut self, val: FramePolicy) -> Self {
+        self.frame_policy = val;
+        self
+    }
+
+    /// Set the pixel format of captured frames. Supported formats are `B8G8R8A8UIntNormalized`
+    /// (default) and `R16G16B16A16Float`, which preserves HDR content.
+    ///
+    /// Note that frames in `R16G16B16A16Float` format use 8 bytes per pixel (see
+    /// `Frame::bytes_per_pixel()`), which most image libraries won't handle directly.
+    pub fn set_pixel_format(mut self, val: DirectXPixelFormat) -> Self {
+        self.pixel_format = val;
+        self
+    }
+
+    /// Convert frames to another format on the GPU. The only supported conversion is from the
+    /// default `B8G8R8A8UIntNormalized` pixel format to `OutputFormat::Nv12`, building the
+    /// capture fails for other combinations.
+    ///
+    /// NV12 frames always have even dimensions, so the last row/column of the captured content
+    /// might be cut off. Dirty regions are not reported for converted frames.
+    pub fn set_output_format(mut self, val: OutputFormat) -> Self {
+        self.output_format = val;
+        self
+    }
+
+    /// Include secondary windows of the captured window's process (e.g. popups, menus and tool
+    /// windows) in the capture. This requires Windows 11 24H2 (build 26100) or newer, on older
+    /// systems only a warning is logged.
+    pub fn set_include_secondary_windows(mut self, val: bool) -> Self {
+        self.include_secondary_windows = val;
+        self
+    }
+
+    /// Ask the OS to produce frames at most once per the given interval. Unlike `set_max_fps`,
+    /// which drops frames on the client side, this makes the OS generate fewer frames, saving
+    /// power. This requires Windows 11 (build 22621) or newer, on older systems only a warning
+    /// is logged.
+    pub fn set_min_update_interval(mut self, val: Option<Duration>) -> Self {
+        self.min_update_interval = val;
+        self
+    }
+
+    /// Skip the given number of frames after the capture is started (or the target is changed),
+    /// e.g. because some applications render black frames at first. Frames with zero size are
+    /// always skipped, regardless of this setting.
+    pub fn set_warmup_frames(mut self, val: u32) -> Self {
+        self.warmup_frames = val;
+        self
+    }
+
+    /// Whether frames should arrive on a thread pool thread (the default), as opposed to the
+    /// thread creating the capture.
+    ///
+    /// The default works on any thread regardless of its apartment, as `grab()` only reads frames
+    /// from a channel. When disabled, the creating thread must have a `DispatcherQueue` and pump
+    /// messages, otherwise no frames are delivered - this is meant for GUI applications which want
+    /// `on_frame` callbacks to be called on their UI thread.
+    pub fn set_secondary_thread(mut self, val: bool) -> Self {
+        self.secondary_thread = val;
+        self
+    }
+
+    /// Always use the WARP software renderer instead of a hardware D3D11 device. This is slower,
+    /// but works in environments without a usable GPU (e.g. headless CI machines or some remote
+    /// desktop sessions).
+    pub fn set_warp(mut self, val: bool) -> Self {
+        self.warp = val;
+        self
+    }
+
+    /// Record the position and visibility of the mouse cursor in `Frame::cursor`, e.g. to draw a
+    /// custom cursor. This is independent of `set_is_cursor_capture_enabled`, which controls
+    /// whether the cursor is rendered into the frame.
+    pub fn set_capture_cursor_metadata(mut self, val: bool) -> Self {
+        self.capture_cursor_metadata = val;
+        self
+    }
+
+    /// Set how long `Capture::grab()` waits for a frame before checking whether the capture
+    /// target has been closed. Shorter intervals detect closed targets sooner, longer ones wake up
+    /// the grabbing thread less often. Defaults to 50ms.
+    pub fn set_close_poll_interval(mut self, val: Duration) -> Self {
+        self.close_poll_interval = val;
+        self
+    }
+
     pub fn build(self) -> Result<Capture> {
-        Capture::new(
-            self.capturable,
-            self.is_cursor_capture_enabled,
-            self.is_border_required,
-            self.cpu_access,
-        )
+        Capture::new(self)
+    }
+
+    /// Build the capture and start capturing frames right away. Use `build()` and
+    /// `Capture::start()` instead if something has to be set up in between, e.g. GPU interop with
+    /// the capture's device.
+    pub fn build_and_start(self) -> Result<Capture> {
+        let mut capture = self.build()?;
+        capture.start()?;
+        Ok(capture)
     }
 }
 
+/// Format of the frames returned by `Capture::grab()`, see `CaptureBuilder::set_output_format`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Frames have the pixel format they are captured in, see `CaptureBuilder::set_pixel_format`.
+    #[default]
+    Native,
+    /// Frames are converted to NV12 (BT.709, studio range) on the GPU, which is the input format
+    /// expected by most hardware video encoders. See `Frame::nv12_planes()` for the layout.
+    Nv12,
+}
+
+/// Policy for handling frames which have arrived since the last grab.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramePolicy {
+    /// Frames are returned in order of arrival, so a slow consumer might receive stale frames.
+    #[default]
+    Buffered,
+    /// Only the most recent frame is returned, older frames are dropped.
+    LatestOnly,
+}
+
+/// Number of pixels to exclude from each side of the capture box.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientBoxInset {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl ClientBoxInset {
+    fn apply(&self, capture_box: D3D11_BOX) -> D3D11_BOX {
+        let left = (capture_box.left + self.left).min(capture_box.right);
+        let top = (capture_box.top + self.top).min(capture_box.bottom);
+        D3D11_BOX {
+            left,
+            top,
+            right: capture_box.right.saturating_sub(self.right).max(left),
+            bottom: capture_box.bottom.saturating_sub(self.bottom).max(top),
+            ..capture_box
+        }
+    }
+}
+
+/// The reason a `Capture` has been stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The capture target (e.g. a window) has been closed.
+    WindowClosed,
+    /// `Capture::stop()` has been called.
+    StoppedByUser,
+    /// The channel delivering frames or close notifications has been disconnected.
+    ChannelDisconnected,
+    /// The D3D device has been removed or reset (e.g. after a driver update or a GPU timeout).
+    DeviceLost,
+}
+
+/// Result of a non-blocking `Capture::try_grab()`.
+pub enum MaybeFrame {
+    /// A new frame has been captured.
+    Some(Frame),
+    /// No new frames are available yet.
+    Pending,
+    /// No new frames are available because the capture target is minimized.
+    Minimized,
+    /// No more frames will be produced (e.g. the window was closed).
+    None,
+}
+
+impl MaybeFrame {
+    /// Whether a new frame has been captured.
+    #[must_use]
+    pub fn is_some(&self) -> bool {
+        matches!(self, MaybeFrame::Some(_))
+    }
+
+    /// Whether there is no new frame yet, but there might be one later. This includes the
+    /// `Minimized` case.
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        matches!(self, MaybeFrame::Pending | MaybeFrame::Minimized)
+    }
+
+    /// Whether no more frames will be produced.
+    #[must_use]
+    pub fn is_none(&self) -> bool {
+        matches!(self, MaybeFrame::None)
+    }
+
+    /// Get the captured frame, if any. Use `is_none()` beforehand to tell a finished capture
+    /// apart from a pending frame.
+    #[must_use]
+    pub fn into_option(self) -> Option<Frame> {
+        match self {
+            MaybeFrame::Some(frame) => Some(frame),
+            MaybeFrame::Pending | MaybeFrame::Minimized | MaybeFrame::None => None,
+        }
+    }
+}
+
+/// A frame received from the frame pool, along with its arrival sequence number.
+type ArrivedFrame = (Direct3D11CaptureFrame, u64);
+
+enum NextFrame {
+    Frame(ArrivedFrame),
+    Pending,
+    Closed,
+}
+
 /// Represents a Capture session.
 pub struct Capture {
     d3d: D3D,
     capturable: Box<dyn Capturable>,
     capture_box: D3D11_BOX,
+    client_box_inset: ClientBoxInset,
+    // see `CaptureBuilder::set_capture_box`
+    capture_box_override: Option<D3D11_BOX>,
+    // see `CaptureBuilder::set_client_crop`
+    client_crop: Option<RECT>,
     capture_done_signal: Receiver<()>,
     frame_pool: Direct3D11CaptureFramePool,
-    frame_source: Receiver<Option<Direct3D11CaptureFrame>>,
+    frame_source: Receiver<Option<ArrivedFrame>>,
+    capture_item: GraphicsCaptureItem,
     session: GraphicsCaptureSession,
+    // kept to recreate the frame pool and the session in `restart()`
+    session_options: SessionOptions,
+    secondary_thread: bool,
+    max_fps: Option<f32>,
+    on_frame: Option<Arc<Mutex<FrameCallback>>>,
+    on_close: Option<CloseCallback>,
+    // counts frames arriving from the frame pool, including the dropped ones
+    frame_counter: Arc<AtomicU64>,
     cpu_access: bool,
+    texture_sharing: TextureSharing,
+    output_size: Option<(u32, u32)>,
+    scaler: Option<Scaler>,
+    frame_policy: FramePolicy,
+    pixel_format: DirectXPixelFormat,
+    output_format: OutputFormat,
+    capture_cursor_metadata: bool,
+    border_change_failed: bool,
+    warmup_frames: u32,
+    // number of frames left to skip, see `CaptureBuilder::set_warmup_frames`
+    warmup_remaining: u32,
+    // set when frames were dropped, so dirty regions of the next frame are not enough to update
+    // the staging texture
+    full_copy_required: bool,
+    // sequence number of the last processed frame, see `is_contiguous`
+    last_sequence: Option<u64>,
     staging_texture: Option<ID3D11Texture2D>,
     content_size: SizeInt32,
+    item_size: SizeInt32,
+    started: bool,
     stopped: bool,
+    close_reason: Option<CloseReason>,
+    close_poll_interval: Duration,
+    last_close_poll: Instant,
 }
 
 impl Capture {
@@ -94,143 +589,632 @@ impl Capture {
     /// frame pool / capture session.
     ///
     /// Note that this will not start capturing yet. Call `start()` to actually start receiving frames.
-    pub(crate) fn new(
-        capturable: Box<dyn Capturable>,
-        is_cursor_capture_enabled: bool,
-        is_border_required: bool,
-        cpu_access: bool,
-    ) -> Result<Self> {
-        let d3d = D3D::new()?;
+    pub(crate) fn new(builder: CaptureBuilder) -> Result<Self> {
+        let CaptureBuilder {
+            capturable,
+            is_cursor_capture_enabled,
+            is_border_required,
+            allow_border_change_failure,
+            cpu_access,
+            client_box_inset,
+            capture_box: capture_box_override,
+            client_crop,
+            on_frame,
+            on_close,
+            max_fps,
+            texture_sharing,
+            dirty_region_only,
+            output_size,
+            frame_policy,
+            pixel_format,
+            output_format,
+            include_secondary_windows,
+            min_update_interval,
+            warmup_frames,
+            secondary_thread,
+            warp,
+            capture_cursor_metadata,
+            close_poll_interval,
+        } = builder;
+        if !GraphicsCaptureSession::IsSupported().unwrap_or(false) {
+            return Err(Error::CaptureNotSupported);
+        }
+        check_max_fps(max_fps)?;
+        if output_format == OutputFormat::Nv12
+            && pixel_format != DirectXPixelFormat::B8G8R8A8UIntNormalized
+        {
+            return Err(windows::core::Error::new(
+                windows::Win32::Foundation::E_INVALIDARG,
+                "NV12 output is only supported for the B8G8R8A8UIntNormalized pixel format",
+            )
+            .into());
+        }
+        let d3d = D3D::new(warp)?;
         let capture_item = capturable.create_capture_item()?;
         let capture_item_size = capture_item.Size()?;
+        if let Some(capture_box) = capture_box_override {
+            check_capture_box(&capture_box, capture_item_size)?;
+        } else if let Some(crop) = client_crop {
+            check_client_crop(&crop, capturable.get_client_box()?)?;
+        }
 
-        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
-            &d3d.direct3d_device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
-            1,
-            capture_item_size,
+        let frame_pool =
+            create_frame_pool(&d3d, pixel_format, capture_item_size, secondary_thread)?;
+        let on_frame = on_frame.map(|callback| Arc::new(Mutex::new(callback)));
+        let frame_counter = Arc::new(AtomicU64::new(0));
+        let frame_source = register_frame_handler(
+            &frame_pool,
+            max_fps,
+            on_frame.clone(),
+            frame_counter.clone(),
         )?;
 
-        let session = frame_pool.CreateCaptureSession(&capture_item)?;
-        session.SetIsCursorCaptureEnabled(is_cursor_capture_enabled)?;
-        if !is_border_required {
-            if let Err(e) = session.SetIsBorderRequired(is_border_required) {
-                log::warn!(
-                    "got '{}' when trying to disable the capture border - see https://github.com/modelflat/zbl/pull/4 for more info",
-                    e
-                );
-            }
-        }
+        let mut session_options = SessionOptions {
+            is_cursor_capture_enabled,
+            is_border_required,
+            allow_border_change_failure,
+            include_secondary_windows,
+            min_update_interval,
+            dirty_region_only,
+        };
+        let (session, border_change_failed) =
+            session_options.create_session(&frame_pool, &capture_item)?;
 
-        let (sender, receiver) = sync_channel(1 << 5);
-        frame_pool.FrameArrived(
-            &TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new(
-                move |frame_pool, _| {
-                    let frame_pool = frame_pool.as_ref().unwrap();
-                    let frame = frame_pool.TryGetNextFrame()?;
-                    let ts = frame.SystemRelativeTime()?;
-                    match sender.try_send(Some(frame)) {
-                        Err(TrySendError::Full(_)) => {
-                            // TODO keep track of these frames?
-                            log::info!("dropping frame {}", ts.Duration);
-                        }
-                        Err(TrySendError::Disconnected(_)) => {
-                            log::info!("frame receiver disconnected");
-                        }
-                        _ => {}
-                    }
-                    Ok(())
-                },
-            ),
+        let capture_box = resolve_capture_box(
+            capturable.as_ref(),
+            capture_item_size,
+            capture_box_override,
+            client_crop,
+            client_box_inset,
         )?;
-
-        let capture_box = capturable.get_client_box()?;
         let capture_done_signal = capturable.get_close_notification_channel();
 
         Ok(Self {
             d3d,
             capturable,
             capture_box,
+            client_box_inset,
+            capture_box_override,
+            client_crop,
             capture_done_signal,
             frame_pool,
-            frame_source: receiver,
+            frame_source,
+            capture_item,
             session,
+            session_options,
+            secondary_thread,
+            max_fps,
+            on_frame,
+            on_close,
+            frame_counter,
             cpu_access,
+            texture_sharing,
+            output_size,
+            scaler: None,
+            frame_policy,
+            pixel_format,
+            output_format,
+            capture_cursor_metadata,
+            border_change_failed,
+            warmup_frames,
+            warmup_remaining: warmup_frames,
+            full_copy_required: false,
+            last_sequence: None,
             staging_texture: None,
             content_size: Default::default(),
+            item_size: capture_item_size,
+            started: false,
             stopped: false,
+            close_reason: None,
+            close_poll_interval,
+            last_close_poll: Instant::now(),
         })
     }
 
-    /// Get D3D contexts
+    /// Get the D3D11 device and context used by this capture. This is the supported entry point
+    /// for GPU interop: textures of frames returned by `grab()` belong to this device.
     pub fn d3d(&mut self) -> &mut D3D {
         &mut self.d3d
     }
 
+    /// Get the Windows.Graphics.Capture item of the capture target, e.g. to query its current
+    /// `Size()` or `DisplayName()`. This is the item the current session has been created for.
+    pub fn capture_item(&self) -> &GraphicsCaptureItem {
+        &self.capture_item
+    }
+
+    /// Get the underlying capture session, e.g. to use Windows.Graphics.Capture APIs which are not
+    /// wrapped by zbl.
+    ///
+    /// Changing settings of the session directly (e.g. `SetIsBorderRequired`) bypasses zbl: such
+    /// changes are not reflected by `is_border_required()` and friends, and are lost when the
+    /// session is recreated by `restart()` or `set_capturable()`. Prefer the `Capture` setters
+    /// where available.
+    pub fn session(&self) -> &GraphicsCaptureSession {
+        &self.session
+    }
+
+    /// Get the D3D11 device used by this capture, see `d3d()`.
+    pub fn device(&self) -> &ID3D11Device {
+        &self.d3d.device
+    }
+
+    /// Get the immediate context of the device used by this capture, see `d3d()`.
+    pub fn context(&self) -> &ID3D11DeviceContext {
+        &self.d3d.context
+    }
+
     /// Whether the backing buffer of this instance of `Capture` is CPU-accessible.
     pub fn has_cpu_access(&self) -> bool {
         self.cpu_access
     }
 
+    /// Get the pixel format of captured frames.
+    pub fn pixel_format(&self) -> DirectXPixelFormat {
+        self.pixel_format
+    }
+
+    /// Get the format of the frames returned by `grab()`, see `CaptureBuilder::set_output_format`.
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
     /// Get attached capturable.
     pub fn capturable(&self) -> &dyn Capturable {
         self.capturable.as_ref()
     }
 
-    /// Start capturing frames.
-    pub fn start(&self) -> Result<()> {
-        self.session.StartCapture()
+    /// Get the size of the capture item (e.g. the whole window including its frame) as reported
+    /// when the frame pool was last created. This is known before the first frame arrives, but
+    /// differs from the size of the frames returned by `grab()`, see `capture_box()`.
+    pub fn initial_size(&self) -> SizeInt32 {
+        self.item_size
+    }
+
+    /// Get the region of the capture item which is copied into frames, i.e. the client area of
+    /// windows (excluding the insets set by `CaptureBuilder::set_client_box_inset`).
+    pub fn capture_box(&self) -> D3D11_BOX {
+        self.capture_box
+    }
+
+    /// Get the last known (width, height) of the captured content, i.e. the size of the frames
+    /// returned by `grab()`. This is known before the first frame arrives.
+    pub fn content_size(&self) -> (u32, u32) {
+        self.output_size((
+            self.capture_box.right - self.capture_box.left,
+            self.capture_box.bottom - self.capture_box.top,
+        ))
+    }
+
+    /// Switch to another capture target, keeping the D3D device and the frame pool.
+    ///
+    /// The current session is closed and a new one is created with the same settings, so the
+    /// capture has to be started again with `start()`. Frames of the previous target which have
+    /// not been grabbed yet are discarded.
+    pub fn set_capturable(&mut self, capturable: Box<dyn Capturable>) -> Result<()> {
+        if self.stopped {
+            return Err(windows::core::Error::new(
+                windows::Win32::Foundation::E_ILLEGAL_METHOD_CALL,
+                "cannot change the capture target of a stopped capture",
+            )
+            .into());
+        }
+        let capture_item = capturable.create_capture_item()?;
+        let capture_item_size = capture_item.Size()?;
+        if let Some(capture_box) = self.capture_box_override {
+            check_capture_box(&capture_box, capture_item_size)?;
+        }
+        let capture_box = resolve_capture_box(
+            capturable.as_ref(),
+            capture_item_size,
+            self.capture_box_override,
+            self.client_crop,
+            self.client_box_inset,
+        )?;
+
+        self.session.Close()?;
+
+        self.frame_pool.Recreate(
+            &self.d3d.direct3d_device,
+            self.pixel_format,
+            1,
+            capture_item_size,
+        )?;
+        let (session, border_change_failed) = self
+            .session_options
+            .create_session(&self.frame_pool, &capture_item)?;
+        self.border_change_failed = border_change_failed;
+        while let Ok(Some(_)) = self.frame_source.try_recv() {}
+
+        self.capture_done_signal = capturable.get_close_notification_channel();
+        self.capturable.release_close_notification_channels();
+        self.capturable = capturable;
+        self.capture_box = capture_box;
+        self.item_size = capture_item_size;
+        self.capture_item = capture_item;
+        self.session = session;
+        self.scaler = None;
+        self.staging_texture = None;
+        self.content_size = Default::default();
+        self.full_copy_required = false;
+        self.last_sequence = None;
+        self.warmup_remaining = self.warmup_frames;
+        self.started = false;
+        Ok(())
+    }
+
+    /// Map a pixel position in captured frames to screen coordinates, e.g. to click on something
+    /// detected in a frame. Takes scaling by `CaptureBuilder::set_output_size` into account.
+    ///
+    /// Returns `None` if the screen position of the capture target is unknown (e.g. for items
+    /// picked by the user). Note that the window might have moved since the frame was captured.
+    pub fn frame_to_screen(&self, x: u32, y: u32) -> Option<(i32, i32)> {
+        let (origin_x, origin_y) = self.capturable.get_screen_origin()?;
+        let (scale_x, scale_y) = self.frame_scale();
+        Some((
+            origin_x + self.capture_box.left as i32 + (x as f64 / scale_x) as i32,
+            origin_y + self.capture_box.top as i32 + (y as f64 / scale_y) as i32,
+        ))
+    }
+
+    /// Map a screen position to frame coordinates, the inverse of `frame_to_screen`. The result
+    /// is outside of the frame if the position is not within the captured area.
+    pub fn screen_to_frame(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (origin_x, origin_y) = self.capturable.get_screen_origin()?;
+        let (scale_x, scale_y) = self.frame_scale();
+        let x = x - origin_x - self.capture_box.left as i32;
+        let y = y - origin_y - self.capture_box.top as i32;
+        Some((
+            (x as f64 * scale_x).floor() as i32,
+            (y as f64 * scale_y).floor() as i32,
+        ))
+    }
+
+    /// Start capturing frames. Calling this on a started capture has no effect.
+    pub fn start(&mut self) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.session.StartCapture()?;
+        self.started = true;
+        Ok(())
+    }
+
+    /// Restart a stopped capture, e.g. after the target window has been closed and reopened with
+    /// the same handle or title. The frame pool and the session are recreated with the same
+    /// settings against the existing D3D device and capture target, which is then started right
+    /// away. On a capture which has not been stopped this is the same as `start()`.
+    ///
+    /// Frame timestamps reset along with the session: frames captured after the restart are not
+    /// continuous with the previous ones, and warm-up frames are skipped again.
+    pub fn restart(&mut self) -> Result<()> {
+        if !self.stopped {
+            return self.start();
+        }
+        self.capturable.refresh();
+        let capture_item = self.capturable.create_capture_item()?;
+        let capture_item_size = capture_item.Size()?;
+        let capture_box = resolve_capture_box(
+            self.capturable.as_ref(),
+            capture_item_size,
+            self.capture_box_override,
+            self.client_crop,
+            self.client_box_inset,
+        )?;
+
+        let frame_pool = create_frame_pool(
+            &self.d3d,
+            self.pixel_format,
+            capture_item_size,
+            self.secondary_thread,
+        )?;
+        let frame_source = register_frame_handler(
+            &frame_pool,
+            self.max_fps,
+            self.on_frame.clone(),
+            self.frame_counter.clone(),
+        )?;
+        let (session, border_change_failed) = self
+            .session_options
+            .create_session(&frame_pool, &capture_item)?;
+
+        self.capture_done_signal = self.capturable.get_close_notification_channel();
+        self.frame_pool = frame_pool;
+        self.frame_source = frame_source;
+        self.capture_item = capture_item;
+        self.session = session;
+        self.border_change_failed = border_change_failed;
+        self.capture_box = capture_box;
+        self.item_size = capture_item_size;
+        self.scaler = None;
+        self.staging_texture = None;
+        self.content_size = Default::default();
+        self.full_copy_required = false;
+        self.last_sequence = None;
+        self.warmup_remaining = self.warmup_frames;
+        self.close_reason = None;
+        self.last_close_poll = Instant::now();
+        self.stopped = false;
+        self.started = false;
+        self.start()
+    }
+
+    /// Whether `start()` has been called.
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Enable or disable capturing the cursor on a live session. Takes effect on subsequent frames.
+    pub fn set_cursor_capture_enabled(&mut self, val: bool) -> Result<()> {
+        self.session.SetIsCursorCaptureEnabled(val)?;
+        self.session_options.is_cursor_capture_enabled = val;
+        Ok(())
+    }
+
+    /// Whether the cursor is currently captured, as reported by the session.
+    pub fn is_cursor_capture_enabled(&self) -> Result<bool> {
+        Ok(self.session.IsCursorCaptureEnabled()?)
+    }
+
+    /// Enable or disable the capture border on a live session. Returns whether the change has
+    /// been applied.
+    ///
+    /// If the OS does not support changing the border, this fails if the capture was built with
+    /// `set_allow_border_change_failure(false)`. Otherwise, a warning is logged, the border is left
+    /// as is and `false` is returned.
+    pub fn set_border_required(&mut self, val: bool) -> Result<bool> {
+        match set_session_border_required(&self.session, val) {
+            Ok(()) => self.border_change_failed = false,
+            Err(e) if !self.session_options.allow_border_change_failure => return Err(e.into()),
+            Err(_) => self.border_change_failed = true,
+        }
+        self.session_options.is_border_required = val;
+        Ok(!self.border_change_failed)
+    }
+
+    /// Whether the last attempt to change the capture border (either when building the capture
+    /// or via `set_border_required()`) has failed, i.e. the border is drawn regardless.
+    pub fn border_change_failed(&self) -> bool {
+        self.border_change_failed
+    }
+
+    /// Whether the capture border is currently drawn around the capture target.
+    pub fn is_border_required(&self) -> bool {
+        // the border is always drawn on systems that don't support this property
+        self.session.IsBorderRequired().unwrap_or(true)
     }
 
     /// Grab current capture frame.
     ///
+    /// Frames with zero size, as well as the warm-up frames set by
+    /// `CaptureBuilder::set_warmup_frames`, are skipped.
+    ///
     /// **This method blocks if there is no frames in the frame pool** (happens when application's window
-    /// is minimized, for example).
+    /// is minimized, for example). Use `try_grab()` to avoid blocking.
     ///
     /// Returns:
     /// * `Ok(Some(...))` if there is a frame and it's been successfully captured;
     /// * `Ok(None)` if no frames can be received (e.g. when the window was closed).
     /// * `Err(...)` if an error has occured while capturing a frame.
+    ///
+    /// If the D3D device is lost (e.g. after a driver update or a GPU timeout), `Error::DeviceLost`
+    /// is returned once and the capture is stopped with `CloseReason::DeviceLost`. Such a capture
+    /// can't be recovered, a new one has to be created.
     pub fn grab(&mut self) -> Result<Option<Frame>> {
-        match self.receive_next_frame()? {
-            Some(frame) => {
-                let original_texture: ID3D11Texture2D =
-                    get_dxgi_interface_from_object(&frame.Surface()?)?;
-
-                // TODO can we avoid copying data into staging texture when DirectX interop is enabled?
-                // currently it doesn't work because of the following error:
-                //   OpenCL: clCreateFromD3D11Texture2DNV failed in function 'cv::directx::__convertFromD3D11Texture2DNV'
-                // which seems to be in turn caused by presence of D3D11_RESOURCE_MISC_SHARED_NTHANDLE misc flag in the
-                // original frame texture
-                self.copy_to_staging(&original_texture)?;
-
-                let staging_texture = self
-                    .staging_texture
-                    .clone()
-                    .expect("staging texture should be initialized at this point");
-
-                if self.cpu_access {
-                    let ptr = self.d3d.map_unmap_texture(&staging_texture)?;
-                    Ok(Some(Frame::new_mapped(staging_texture, ptr)))
-                } else {
-                    Ok(Some(Frame::new(staging_texture)))
-                }
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+        while let Some((frame, sequence)) = self.receive_next_frame()? {
+            let frame = self
+                .process_frame(frame, sequence)
+                .map_err(|e| self.check_device_lost(e))?;
+            if frame.is_some() {
+                return Ok(frame);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like `grab()`, but also returns the device context the frame texture belongs to, e.g. to
+    /// run custom GPU work on the frame. See `d3d()` for the device.
+    pub fn grab_with_context(&mut self) -> Result<Option<(Frame, &ID3D11DeviceContext)>> {
+        Ok(self.grab()?.map(|frame| (frame, &self.d3d.context)))
+    }
+
+    /// Try to grab current capture frame without blocking.
+    ///
+    /// Returns:
+    /// * `Ok(MaybeFrame::Some(...))` if there is a frame and it's been successfully captured;
+    /// * `Ok(MaybeFrame::Pending)` if there are no new frames yet;
+    /// * `Ok(MaybeFrame::Minimized)` if there are no new frames because the target is minimized;
+    /// * `Ok(MaybeFrame::None)` if no frames can be received (e.g. when the window was closed).
+    /// * `Err(...)` if an error has occured while capturing a frame.
+    ///
+    /// Device loss is handled the same way as in `grab()`.
+    pub fn try_grab(&mut self) -> Result<MaybeFrame> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+        match self.poll_next_frame(None)? {
+            NextFrame::Frame((frame, sequence)) => Ok(self
+                .process_frame(frame, sequence)
+                .map_err(|e| self.check_device_lost(e))?
+                .map_or(MaybeFrame::Pending, MaybeFrame::Some)),
+            NextFrame::Pending if self.capturable.is_minimized() => Ok(MaybeFrame::Minimized),
+            NextFrame::Pending => Ok(MaybeFrame::Pending),
+            NextFrame::Closed => Ok(MaybeFrame::None),
+        }
+    }
+
+    /// Grab up to `n` frames, copying each into an `OwnedFrame`. Fewer frames are returned if the
+    /// capture ends in the meantime. Requires CPU access.
+    pub fn grab_n(&mut self, n: usize) -> Result<Vec<OwnedFrame>> {
+        let mut frames = Vec::with_capacity(n);
+        while frames.len() < n {
+            match self.grab()? {
+                Some(frame) => frames.push(frame.to_owned()?),
+                None => break,
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Start capturing if needed and wait until the capture target produces a frame, e.g. to
+    /// confirm that a window which has just appeared is actually rendering. Polls `try_grab()`
+    /// at `frame_interval_hint()`, and the received frame is discarded.
+    ///
+    /// Returns `false` if no frame has arrived within `timeout` or the capture has ended.
+    pub fn wait_for_first_frame(&mut self, timeout: Duration) -> Result<bool> {
+        self.start()?;
+        let deadline = Instant::now() + timeout;
+        let poll_interval = self
+            .frame_interval_hint()
+            .unwrap_or(FIRST_FRAME_POLL_INTERVAL);
+        loop {
+            let frame = self.try_grab()?;
+            if !frame.is_pending() {
+                return Ok(frame.is_some());
             }
-            None => Ok(None),
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            std::thread::sleep(poll_interval.min(deadline - now));
+        }
+    }
+
+    /// Discard all frames which have arrived but haven't been grabbed yet, returning their count.
+    /// This can be used to resync to the most recent content after not grabbing for a while.
+    pub fn drain(&mut self) -> usize {
+        let mut count = 0;
+        while let Ok(Some((frame, _))) = self.frame_source.try_recv() {
+            let _ = frame.Close();
+            count += 1;
+        }
+        if count > 0 {
+            // dirty regions of the next frame are relative to the discarded ones
+            self.full_copy_required = true;
+        }
+        count
+    }
+
+    /// Whether the capture target is currently able to produce frames. This can be used to report
+    /// a paused capture instead of waiting on `grab()`.
+    pub fn target_ready(&self) -> bool {
+        self.capturable.is_ready()
+    }
+
+    /// Get the expected time between frames, e.g. to sleep between `try_grab()` calls instead of
+    /// polling in a busy loop. This is the refresh interval of the display showing the capture
+    /// target, limited by `CaptureBuilder::set_max_fps` and `set_min_update_interval`.
+    ///
+    /// Returns `None` if the refresh rate of the display can't be determined. Note that frames
+    /// only arrive when the content changes, so there might be no frame after this interval.
+    pub fn frame_interval_hint(&self) -> Option<Duration> {
+        let refresh_rate = self.capturable.get_refresh_rate()?;
+        let mut interval = Duration::from_secs_f64(1.0 / refresh_rate as f64);
+        if let Some(max_fps) = self.max_fps.filter(|&fps| fps > 0.0) {
+            interval = interval.max(Duration::from_secs_f64(1.0 / max_fps as f64));
+        }
+        if let Some(min_update_interval) = self.session_options.min_update_interval {
+            interval = interval.max(min_update_interval);
         }
+        Some(interval)
+    }
+
+    /// Ask the capture target to repaint itself in order to get a new frame.
+    ///
+    /// Windows.Graphics.Capture only produces frames when the content changes, and it does so for
+    /// windows which are behind others too. However, some applications stop rendering when they are
+    /// not focused or fully occluded, so `grab()` blocks until they happen to repaint. For windows
+    /// this invalidates the whole window, which makes it receive `WM_PAINT` as soon as it processes
+    /// its messages. Does nothing for displays.
+    pub fn force_redraw(&self) -> Result<()> {
+        self.capturable.request_redraw()
+    }
+
+    /// Whether the capture target is currently minimized, in which case no frames are produced.
+    pub fn is_target_minimized(&self) -> bool {
+        self.capturable.is_minimized()
+    }
+
+    /// Turn this capture into a channel of CPU-side frames which can be consumed from other threads.
+    ///
+    /// `Capture` holds COM objects and can't be sent between threads, so the returned `FramePump`
+    /// has to be run on the thread owning the capture. It copies each frame into an `OwnedFrame`
+    /// and sends it to the returned `Receiver`. The `CaptureHandle` can be used to stop the
+    /// capture from any thread. Requires CPU access.
+    pub fn into_channel(self) -> (CaptureHandle, Receiver<OwnedFrame>, FramePump) {
+        channel::into_channel(self, 1 << 5)
     }
 
     /// Stops the capture.
     ///
-    /// This `Capture` instance cannot be reused after that (i.e. calling `start()` again will
-    /// **not** produce more frames).
+    /// Calling `start()` again will **not** produce more frames, use `restart()` instead.
     pub fn stop(&mut self) -> Result<()> {
+        self.stop_with_reason(CloseReason::StoppedByUser)
+    }
+
+    /// Whether the capture has been stopped, either explicitly or because the target has gone away.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Get the reason this capture has been stopped. Returns `None` if it's still running.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.close_reason
+    }
+
+    fn stop_with_reason(&mut self, reason: CloseReason) -> Result<()> {
         self.stopped = true;
+        self.close_reason.get_or_insert(reason);
+        self.capturable.release_close_notification_channels();
+        if reason != CloseReason::StoppedByUser {
+            if let Some(on_close) = self.on_close.take() {
+                on_close();
+            }
+        }
         self.session.Close()?;
         self.frame_pool.Close()?;
         Ok(())
     }
 
+    /// Stop the capture if the error indicates that the D3D device has been lost, as every
+    /// subsequent D3D call would fail the same way.
+    fn check_device_lost(&mut self, error: Error) -> Error {
+        match error {
+            Error::Windows(e)
+                if e.code() == DXGI_ERROR_DEVICE_REMOVED
+                    || e.code() == DXGI_ERROR_DEVICE_RESET
+                    || e.code() == DXGI_ERROR_DEVICE_HUNG =>
+            {
+                log::error!("D3D device has been lost: {}", e);
+                if let Err(close_error) = self.stop_with_reason(CloseReason::DeviceLost) {
+                    log::warn!("failed to close the capture session: {}", close_error);
+                }
+                Error::DeviceLost(e)
+            }
+            e => e,
+        }
+    }
+
+    /// Get the size the content of given size should be scaled to, if any.
+    fn scaled_size(&self, size: (u32, u32)) -> Option<(u32, u32)> {
+        self.output_size
+            .filter(|&(width, height)| width < size.0 || height < size.1)
+    }
+
+    /// Get the size of frames produced from a capture box of given size, taking both scaling and
+    /// the output format into account.
+    fn output_size(&self, box_size: (u32, u32)) -> (u32, u32) {
+        let (width, height) = self.scaled_size(box_size).unwrap_or(box_size);
+        match self.output_format {
+            OutputFormat::Native => (width, height),
+            // NV12 textures must have even dimensions
+            OutputFormat::Nv12 => ((width & !1).max(2), (height & !1).max(2)),
+        }
+    }
+
     fn needs_resize(&self, new_size: SizeInt32) -> bool {
         self.content_size.Width != new_size.Width
             || self.content_size.Height != new_size.Height
@@ -238,40 +1222,197 @@ impl Capture {
     }
 
     fn recreate_frame_pool(&mut self) -> Result<()> {
+        self.capturable.refresh();
         let capture_item = self.capturable.create_capture_item()?;
         let capture_item_size = capture_item.Size()?;
-        self.capture_box = self.capturable.get_client_box()?;
+        self.capture_box = resolve_capture_box(
+            self.capturable.as_ref(),
+            capture_item_size,
+            self.capture_box_override,
+            self.client_crop,
+            self.client_box_inset,
+        )?;
+        self.item_size = capture_item_size;
         self.frame_pool.Recreate(
             &self.d3d.direct3d_device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            self.pixel_format,
             1,
             capture_item_size,
         )?;
         Ok(())
     }
 
-    fn receive_next_frame(&mut self) -> Result<Option<Direct3D11CaptureFrame>> {
+    fn receive_next_frame(&mut self) -> Result<Option<ArrivedFrame>> {
+        loop {
+            match self.poll_next_frame(Some(self.close_poll_interval))? {
+                NextFrame::Frame(frame) => return Ok(Some(frame)),
+                NextFrame::Pending => continue,
+                NextFrame::Closed => return Ok(None),
+            }
+        }
+    }
+
+    /// Receive the next frame, waiting for up to `timeout` for it to arrive.
+    fn poll_next_frame(&mut self, timeout: Option<Duration>) -> Result<NextFrame> {
         if self.stopped {
-            return Ok(None);
+            return Ok(NextFrame::Closed);
         }
-        loop {
-            match self.frame_source.try_recv() {
-                Ok(Some(f)) => return Ok(Some(f)),
-                Err(TryRecvError::Empty) => {
-                    // TODO busy loop? so uncivilized
-                    if let Ok(()) | Err(TryRecvError::Disconnected) =
-                        self.capture_done_signal.try_recv()
+        let received = match timeout {
+            Some(timeout) => self
+                .frame_source
+                .recv_timeout(timeout)
+                .map_err(|e| match e {
+                    RecvTimeoutError::Timeout => TryRecvError::Empty,
+                    RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+                }),
+            None => self.frame_source.try_recv(),
+        };
+        match received {
+            Ok(Some((mut frame, mut sequence))) => {
+                if self.frame_policy == FramePolicy::LatestOnly {
+                    while let Ok(Some((newer_frame, newer_sequence))) = self.frame_source.try_recv()
                     {
-                        self.stop()?;
-                        return Ok(None);
+                        frame = newer_frame;
+                        sequence = newer_sequence;
+                        self.full_copy_required = true;
+                    }
+                }
+                let size = frame.ContentSize()?;
+                if self.warmup_remaining > 0 || size.Width <= 0 || size.Height <= 0 {
+                    self.warmup_remaining = self.warmup_remaining.saturating_sub(1);
+                    self.full_copy_required = true;
+                    frame.Close()?;
+                    return Ok(NextFrame::Pending);
+                }
+                Ok(NextFrame::Frame((frame, sequence)))
+            }
+            Err(TryRecvError::Empty) => {
+                match self.capture_done_signal.try_recv() {
+                    Ok(()) => {
+                        self.stop_with_reason(CloseReason::WindowClosed)?;
+                        return Ok(NextFrame::Closed);
                     }
+                    Err(TryRecvError::Disconnected) => {
+                        self.stop_with_reason(CloseReason::ChannelDisconnected)?;
+                        return Ok(NextFrame::Closed);
+                    }
+                    Err(TryRecvError::Empty) => {}
+                }
+                if self.last_close_poll.elapsed() >= self.close_poll_interval {
+                    if self.capturable.is_closed() {
+                        self.stop_with_reason(CloseReason::WindowClosed)?;
+                        return Ok(NextFrame::Closed);
+                    }
+                    self.last_close_poll = Instant::now();
                 }
-                Ok(None) | Err(TryRecvError::Disconnected) => return Ok(None),
+                Ok(NextFrame::Pending)
+            }
+            Ok(None) | Err(TryRecvError::Disconnected) => {
+                self.stop_with_reason(CloseReason::ChannelDisconnected)?;
+                Ok(NextFrame::Closed)
             }
         }
     }
 
-    fn copy_to_staging(&mut self, frame_texture: &ID3D11Texture2D) -> Result<()> {
+    /// Copy an arrived frame into the staging texture. Returns `None` if the frame had to be skipped.
+    fn process_frame(
+        &mut self,
+        frame: Direct3D11CaptureFrame,
+        sequence: u64,
+    ) -> Result<Option<Frame>> {
+        let original_texture: ID3D11Texture2D = get_dxgi_interface_from_object(&frame.Surface()?)?;
+        let timestamp = system_relative_time_to_duration(frame.SystemRelativeTime()?.Duration);
+        // frames can also be dropped by the frame handler (see `register_frame_handler`), which
+        // shows up as a gap in sequence numbers
+        let full_copy_required =
+            self.full_copy_required || !is_contiguous(self.last_sequence, sequence);
+        let dirty_box = if !self.session_options.dirty_region_only {
+            None
+        } else if full_copy_required {
+            Some(self.capture_box)
+        } else {
+            get_dirty_box(&frame)
+        };
+        self.full_copy_required = false;
+        self.last_sequence = Some(sequence);
+
+        // frame textures can't be handed out directly: they are returned to the frame pool, and
+        // they have the D3D11_RESOURCE_MISC_SHARED_NTHANDLE misc flag set, which makes OpenCL interop fail with
+        //   OpenCL: clCreateFromD3D11Texture2DNV failed in function 'cv::directx::__convertFromD3D11Texture2DNV'
+        // the staging texture is created with flags set by `CaptureBuilder::set_texture_sharing` instead
+        let Some(dirty_rect) = self.copy_to_staging(&original_texture, dirty_box)? else {
+            // the next frame has nothing to be relative to
+            self.full_copy_required = true;
+            frame.Close()?;
+            return Ok(None);
+        };
+
+        let staging_texture = self
+            .staging_texture
+            .clone()
+            .expect("staging texture should be initialized at this point");
+
+        let mut frame = if self.cpu_access {
+            let ptr = self.d3d.map_unmap_texture(&staging_texture)?;
+            Frame::new_mapped(staging_texture, ptr)
+        } else {
+            Frame::new(staging_texture)
+        };
+        frame.dirty_rect = dirty_rect;
+        frame.timestamp = timestamp;
+        frame.sequence = sequence;
+        if self.capture_cursor_metadata {
+            frame.cursor = self.get_cursor_info();
+        }
+        Ok(Some(frame))
+    }
+
+    /// Get the current cursor position in frame coordinates, or `None` if it is outside the
+    /// frame.
+    fn get_cursor_info(&self) -> Option<CursorInfo> {
+        let mut info = CURSORINFO {
+            cbSize: std::mem::size_of::<CURSORINFO>() as u32,
+            ..Default::default()
+        };
+        unsafe { GetCursorInfo(&mut info) }.ok()?;
+        let (x, y) = self.screen_to_frame(info.ptScreenPos.x, info.ptScreenPos.y)?;
+        let (width, height) = self.content_size();
+        if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+            return None;
+        }
+        Some(CursorInfo {
+            x,
+            y,
+            visible: info.flags.0 & CURSOR_SHOWING.0 != 0,
+        })
+    }
+
+    /// Get the scale factors from the capture box to frame coordinates.
+    fn frame_scale(&self) -> (f64, f64) {
+        let box_size = (
+            self.capture_box.right - self.capture_box.left,
+            self.capture_box.bottom - self.capture_box.top,
+        );
+        let (width, height) = self.output_size(box_size);
+        if (width, height) == box_size || box_size.0 == 0 || box_size.1 == 0 {
+            return (1.0, 1.0);
+        }
+        (
+            width as f64 / box_size.0 as f64,
+            height as f64 / box_size.1 as f64,
+        )
+    }
+
+    /// Copy the capture box of the frame texture into the staging texture. If `dirty_box` is set,
+    /// only that region is copied, and it is returned translated to the staging texture coordinates.
+    ///
+    /// Returns `None` if nothing could be copied because the capture box lies outside of the frame
+    /// texture, in which case the frame has to be skipped.
+    fn copy_to_staging(
+        &mut self,
+        frame_texture: &ID3D11Texture2D,
+        dirty_box: Option<D3D11_BOX>,
+    ) -> Result<Option<Option<D3D11_BOX>>> {
         let mut desc = D3D11_TEXTURE2D_DESC::default();
         unsafe { frame_texture.GetDesc(&mut desc) };
         let content_size = SizeInt32 {
@@ -279,30 +1420,572 @@ impl Capture {
             Height: desc.Height as i32,
         };
 
+        // the whole frame has to be copied into a new staging texture
+        let mut dirty_box = dirty_box;
         if self.needs_resize(content_size) {
             self.recreate_frame_pool()?;
-            let new_staging_texture = self.d3d.create_texture(
+            // the client box is computed from the current window metrics, which can be ahead of
+            // this frame while the window is being resized. copying a region outside of the frame
+            // texture would read out of bounds, so the box is clamped to the texture
+            let clamped_box = clamp_box(self.capture_box, desc.Width, desc.Height);
+            if is_empty_box(&clamped_box) {
+                // the frame predates a resize the box is already computed for. the staging
+                // texture is kept as is, and resizing is retried with the next frame
+                log::debug!(
+                    "capture box {:?} is outside of the frame size {}x{}, skipping the frame",
+                    self.capture_box,
+                    desc.Width,
+                    desc.Height
+                );
+                return Ok(None);
+            }
+            if clamped_box != self.capture_box {
+                log::debug!(
+                    "capture box {:?} exceeds the frame size {}x{}, clamping",
+                    self.capture_box,
+                    desc.Width,
+                    desc.Height
+                );
+                self.capture_box = clamped_box;
+            }
+            let box_size = (
                 self.capture_box.right - self.capture_box.left,
                 self.capture_box.bottom - self.capture_box.top,
-                desc.Format,
+            );
+            let (width, height) = self.output_size(box_size);
+            let format = match self.output_format {
+                OutputFormat::Native => desc.Format,
+                OutputFormat::Nv12 => DXGI_FORMAT_NV12,
+            };
+            self.scaler = if (width, height) != box_size || format != desc.Format {
+                Some(Scaler::new(&self.d3d, box_size, (width, height), format)?)
+            } else {
+                None
+            };
+            let new_staging_texture = self.d3d.create_texture(
+                width,
+                height,
+                format,
                 self.cpu_access,
+                self.texture_sharing,
             )?;
             self.staging_texture = Some(new_staging_texture);
             self.content_size = content_size;
+            dirty_box = dirty_box.map(|_| self.capture_box);
         }
 
-        self.d3d.copy_texture(
-            frame_texture,
-            self.staging_texture.as_ref().unwrap(),
-            &self.capture_box,
-        )?;
+        if let Some(scaler) = self.scaler.as_ref() {
+            let scaled_texture = scaler.scale(frame_texture, &self.capture_box)?;
+            let (width, height) = scaler.output_size();
+            self.d3d.copy_texture(
+                scaled_texture,
+                self.staging_texture.as_ref().unwrap(),
+                &D3D11_BOX {
+                    left: 0,
+                    top: 0,
+                    front: 0,
+                    right: width,
+                    bottom: height,
+                    back: 1,
+                },
+            )?;
+            return Ok(Some(None));
+        }
 
-        Ok(())
+        let Some(dirty_box) = dirty_box else {
+            self.d3d.copy_texture(
+                frame_texture,
+                self.staging_texture.as_ref().unwrap(),
+                &self.capture_box,
+            )?;
+            return Ok(Some(None));
+        };
+
+        let cb = self.capture_box;
+        let region = D3D11_BOX {
+            left: dirty_box.left.clamp(cb.left, cb.right),
+            top: dirty_box.top.clamp(cb.top, cb.bottom),
+            right: dirty_box.right.clamp(cb.left, cb.right),
+            bottom: dirty_box.bottom.clamp(cb.top, cb.bottom),
+            ..cb
+        };
+        if region.right > region.left && region.bottom > region.top {
+            self.d3d.copy_texture_to(
+                frame_texture,
+                self.staging_texture.as_ref().unwrap(),
+                &region,
+                region.left - cb.left,
+                region.top - cb.top,
+            )?;
+        }
+
+        Ok(Some(Some(D3D11_BOX {
+            left: region.left - cb.left,
+            top: region.top - cb.top,
+            right: region.right.max(region.left) - cb.left,
+            bottom: region.bottom.max(region.top) - cb.top,
+            front: 0,
+            back: 1,
+        })))
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if !self.stopped {
+            if let Err(e) = self.stop() {
+                log::warn!("failed to stop the capture: {}", e);
+            }
+        }
+        self.capturable.release_close_notification_channels();
+    }
+}
+
+/// Optional Windows.Graphics.Capture features available on this system, see
+/// `session_capabilities()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionCapabilities {
+    /// Whether Windows.Graphics.Capture is supported at all.
+    pub is_supported: bool,
+    /// Whether capturing the cursor can be toggled, see `CaptureBuilder::set_is_cursor_capture_enabled`.
+    pub cursor_toggle: bool,
+    /// Whether the capture border can be disabled, see `CaptureBuilder::set_is_border_required`.
+    pub border_toggle: bool,
+    /// See `CaptureBuilder::set_min_update_interval`.
+    pub min_update_interval: bool,
+    /// See `CaptureBuilder::set_dirty_region_only`.
+    pub dirty_regions: bool,
+    /// See `CaptureBuilder::set_include_secondary_windows`.
+    pub secondary_windows: bool,
+}
+
+/// Check which optional capture session features are available on this system, so that e.g.
+/// disabling the border can be skipped upfront instead of failing with a warning.
+///
+/// Features are reported as unavailable if the check itself fails.
+pub fn session_capabilities() -> SessionCapabilities {
+    let is_property_present = |name: &str| {
+        ApiInformation::IsPropertyPresent(
+            &HSTRING::from("Windows.Graphics.Capture.GraphicsCaptureSession"),
+            &HSTRING::from(name),
+        )
+        .unwrap_or(false)
+    };
+    let is_supported = GraphicsCaptureSession::IsSupported().unwrap_or(false);
+    SessionCapabilities {
+        is_supported,
+        cursor_toggle: is_supported && is_property_present("IsCursorCaptureEnabled"),
+        border_toggle: is_supported && is_property_present("IsBorderRequired"),
+        min_update_interval: is_supported && is_property_present("MinUpdateInterval"),
+        dirty_regions: is_supported && is_property_present("DirtyRegionMode"),
+        secondary_windows: is_supported && is_property_present("IncludeSecondaryWindows"),
+    }
+}
+
+/// Take a single screenshot of the capture target. This initializes zbl, creates a CPU-accessible
+/// capture, waits for the first non-empty frame and stops the capture again.
+///
+/// **This blocks until a frame arrives**, which never happens while a window is minimized.
+pub fn snapshot(capturable: Box<dyn Capturable>) -> Result<OwnedFrame> {
+    crate::init()?;
+    let mut capture = CaptureBuilder::new(capturable)
+        .set_cpu_access(true)
+        .set_frame_policy(FramePolicy::LatestOnly)
+        .build_and_start()?;
+    // frames with zero size are skipped by `grab()`
+    let frame = match capture.grab()? {
+        Some(frame) => frame.to_owned()?,
+        None => return Err(Error::NoFrameCaptured),
+    };
+    capture.stop()?;
+    Ok(frame)
+}
+
+/// Get the region of the capture item to copy: either the box set with
+/// `CaptureBuilder::set_capture_box` clamped to the item size, or the client box of the capturable
+/// with the inset applied.
+fn resolve_capture_box(
+    capturable: &dyn Capturable,
+    item_size: SizeInt32,
+    capture_box: Option<D3D11_BOX>,
+    client_crop: Option<RECT>,
+    inset: ClientBoxInset,
+) -> Result<D3D11_BOX> {
+    if let Some(capture_box) = capture_box {
+        return Ok(clamp_box(
+            capture_box,
+            item_size.Width.max(0) as u32,
+            item_size.Height.max(0) as u32,
+        ));
+    }
+    let client_box = capturable.get_client_box()?;
+    Ok(match client_crop {
+        Some(crop) => crop_client_box(client_box, &crop),
+        None => inset.apply(client_box),
+    })
+}
+
+/// Translate a rect in client coordinates into a box in capture item coordinates, clamped to the
+/// client box.
+fn crop_client_box(client_box: D3D11_BOX, crop: &RECT) -> D3D11_BOX {
+    let translate = |origin: u32, end: u32, offset: i32| (origin + offset.max(0) as u32).min(end);
+    let left = translate(client_box.left, client_box.right, crop.left);
+    let top = translate(client_box.top, client_box.bottom, crop.top);
+    D3D11_BOX {
+        left,
+        top,
+        right: translate(client_box.left, client_box.right, crop.right).max(left),
+        bottom: translate(client_box.top, client_box.bottom, crop.bottom).max(top),
+        ..client_box
+    }
+}
+
+/// Intersect a box with a texture of the given size.
+fn clamp_box(capture_box: D3D11_BOX, width: u32, height: u32) -> D3D11_BOX {
+    let left = capture_box.left.min(width);
+    let top = capture_box.top.min(height);
+    D3D11_BOX {
+        left,
+        top,
+        right: capture_box.right.clamp(left, width),
+        bottom: capture_box.bottom.clamp(top, height),
+        front: 0,
+        back: 1,
+    }
+}
+
+fn is_empty_box(b: &D3D11_BOX) -> bool {
+    b.left >= b.right || b.top >= b.bottom
+}
+
+/// Check that a capture box set with `CaptureBuilder::set_capture_box` is not empty and fits into
+/// the capture item.
+fn check_capture_box(capture_box: &D3D11_BOX, item_size: SizeInt32) -> Result<()> {
+    if capture_box.left >= capture_box.right
+        || capture_box.top >= capture_box.bottom
+        || capture_box.right > item_size.Width.max(0) as u32
+        || capture_box.bottom > item_size.Height.max(0) as u32
+    {
+        return Err(windows::core::Error::new(
+            windows::Win32::Foundation::E_INVALIDARG,
+            format!(
+                "capture box ({}, {})-({}, {}) is empty or does not fit into the capture item of size {}x{}",
+                capture_box.left,
+                capture_box.top,
+                capture_box.right,
+                capture_box.bottom,
+                item_size.Width,
+                item_size.Height
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Check that a frame rate limit set with `CaptureBuilder::set_max_fps` is a positive finite
+/// number.
+fn check_max_fps(max_fps: Option<f32>) -> Result<()> {
+    match max_fps {
+        Some(fps) if !is_valid_max_fps(fps) => Err(windows::core::Error::new(
+            windows::Win32::Foundation::E_INVALIDARG,
+            format!("max_fps must be positive and finite, got {}", fps),
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn is_valid_max_fps(fps: f32) -> bool {
+    fps > 0.0 && fps.is_finite()
+}
+
+/// Check that a crop set with `CaptureBuilder::set_client_crop` is not empty and fits into the
+/// client area.
+fn check_client_crop(crop: &RECT, client_box: D3D11_BOX) -> Result<()> {
+    let width = (client_box.right - client_box.left) as i32;
+    let height = (client_box.bottom - client_box.top) as i32;
+    if crop.left < 0
+        || crop.top < 0
+        || crop.left >= crop.right
+        || crop.top >= crop.bottom
+        || crop.right > width
+        || crop.bottom > height
+    {
+        return Err(windows::core::Error::new(
+            windows::Win32::Foundation::E_INVALIDARG,
+            format!(
+                "client crop ({}, {})-({}, {}) is empty or does not fit into the client area of size {}x{}",
+                crop.left, crop.top, crop.right, crop.bottom, width, height
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Get the bounding box of the dirty regions of the frame. Returns `None` if dirty regions are not
+/// supported by the system, and an empty box if nothing has changed.
+fn get_dirty_box(frame: &Direct3D11CaptureFrame) -> Option<D3D11_BOX> {
+    let regions = frame.DirtyRegions().ok()?;
+    Some(bounding_box(&regions))
+}
+
+/// Get the bounding box of the given rects, or an empty box if there are none.
+fn bounding_box(rects: impl IntoIterator<Item = RectInt32>) -> D3D11_BOX {
+    let mut dirty_box = D3D11_BOX {
+        left: u32::MAX,
+        top: u32::MAX,
+        right: 0,
+        bottom: 0,
+        front: 0,
+        back: 1,
+    };
+    for rect in rects {
+        dirty_box.left = dirty_box.left.min(rect.X.max(0) as u32);
+        dirty_box.top = dirty_box.top.min(rect.Y.max(0) as u32);
+        dirty_box.right = dirty_box.right.max((rect.X + rect.Width).max(0) as u32);
+        dirty_box.bottom = dirty_box.bottom.max((rect.Y + rect.Height).max(0) as u32);
+    }
+    if dirty_box.left > dirty_box.right || dirty_box.top > dirty_box.bottom {
+        dirty_box.left = 0;
+        dirty_box.top = 0;
+        dirty_box.right = 0;
+        dirty_box.bottom = 0;
+    }
+    dirty_box
+}
+
+/// Whether a frame with the given sequence number directly follows the last processed one, i.e.
+/// no frames have been dropped in between. Dirty regions of a frame are only relative to the
+/// previous frame, so they are not enough to update the staging texture otherwise.
+fn is_contiguous(last_sequence: Option<u64>, sequence: u64) -> bool {
+    last_sequence.is_some_and(|last| last.checked_add(1) == Some(sequence))
+}
+
+fn set_session_border_required(
+    session: &GraphicsCaptureSession,
+    val: bool,
+) -> windows::core::Result<()> {
+    session.SetIsBorderRequired(val).inspect_err(|e| {
+        log::warn!(
+            "got '{}' when trying to change the capture border - see https://github.com/modelflat/zbl/pull/4 for more info",
+            e
+        );
+    })
+}
+
+/// Settings of a capture session, kept by `Capture` so that the session can be recreated.
+#[derive(Clone, Copy)]
+struct SessionOptions {
+    is_cursor_capture_enabled: bool,
+    is_border_required: bool,
+    allow_border_change_failure: bool,
+    include_secondary_windows: bool,
+    min_update_interval: Option<Duration>,
+    dirty_region_only: bool,
+}
+
+impl SessionOptions {
+    /// Create a session for `capture_item` and apply these settings to it. Settings which are not
+    /// supported by the OS are skipped with a warning, except for the border if
+    /// `allow_border_change_failure` is not set. Returns the session and whether changing
+    /// the border has failed.
+    fn create_session(
+        &mut self,
+        frame_pool: &Direct3D11CaptureFramePool,
+        capture_item: &GraphicsCaptureItem,
+    ) -> Result<(GraphicsCaptureSession, bool)> {
+        let session = frame_pool.CreateCaptureSession(capture_item)?;
+        session.SetIsCursorCaptureEnabled(self.is_cursor_capture_enabled)?;
+        let border_change_failed = if self.is_border_required {
+            false
+        } else {
+            match set_session_border_required(&session, false) {
+                Ok(()) => false,
+                Err(_) if self.allow_border_change_failure => true,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if self.include_secondary_windows {
+            if let Err(e) = session.SetIncludeSecondaryWindows(true) {
+                log::warn!("secondary windows capture is not supported: {}", e);
+            }
+        }
+        if let Some(interval) = self.min_update_interval {
+            let interval = TimeSpan {
+                Duration: (interval.as_nanos() / 100) as i64,
+            };
+            if let Err(e) = session.SetMinUpdateInterval(interval) {
+                log::warn!("minimum update interval is not supported: {}", e);
+            }
+        }
+        if self.dirty_region_only {
+            if let Err(e) = session.SetDirtyRegionMode(GraphicsCaptureDirtyRegionMode::ReportOnly) {
+                log::warn!(
+                    "dirty regions are not supported, capturing full frames: {}",
+                    e
+                );
+                self.dirty_region_only = false;
+            }
+        }
+        Ok((session, border_change_failed))
     }
 }
 
-fn get_dxgi_interface_from_object<S: Interface, R: Interface>(object: &S) -> Result<R> {
+fn create_frame_pool(
+    d3d: &D3D,
+    pixel_format: DirectXPixelFormat,
+    size: SizeInt32,
+    secondary_thread: bool,
+) -> Result<Direct3D11CaptureFramePool> {
+    let frame_pool = if secondary_thread {
+        Direct3D11CaptureFramePool::CreateFreeThreaded(&d3d.direct3d_device, pixel_format, 1, size)?
+    } else {
+        Direct3D11CaptureFramePool::Create(&d3d.direct3d_device, pixel_format, 1, size)?
+    };
+    Ok(frame_pool)
+}
+
+/// Register a `FrameArrived` handler on `frame_pool`. Frames are passed to `on_frame` if it's set,
+/// otherwise they are sent to the returned receiver.
+fn register_frame_handler(
+    frame_pool: &Direct3D11CaptureFramePool,
+    max_fps: Option<f32>,
+    on_frame: Option<Arc<Mutex<FrameCallback>>>,
+    frame_counter: Arc<AtomicU64>,
+) -> Result<Receiver<Option<ArrivedFrame>>> {
+    let (sender, receiver) = sync_channel(1 << 5);
+    // SystemRelativeTime is measured in 100ns units
+    let min_frame_interval = max_fps.map(|fps| (10_000_000f64 / fps as f64) as i64);
+    let mut last_frame_time: Option<i64> = None;
+    frame_pool.FrameArrived(
+        &TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new(
+            move |frame_pool, _| {
+                let frame_pool = frame_pool.as_ref().unwrap();
+                let frame = frame_pool.TryGetNextFrame()?;
+                let sequence = frame_counter.fetch_add(1, Ordering::Relaxed);
+                let ts = frame.SystemRelativeTime()?.Duration;
+                if let Some(min_frame_interval) = min_frame_interval {
+                    if last_frame_time.is_some_and(|last| ts - last < min_frame_interval) {
+                        return Ok(());
+                    }
+                    last_frame_time = Some(ts);
+                }
+                if let Some(callback) = on_frame.as_ref() {
+                    let texture: ID3D11Texture2D =
+                        get_dxgi_interface_from_object(&frame.Surface()?)?;
+                    let mut raw_frame = Frame::new(texture);
+                    raw_frame.timestamp = system_relative_time_to_duration(ts);
+                    raw_frame.sequence = sequence;
+                    if let Ok(mut callback) = callback.lock() {
+                        (*callback)(&raw_frame);
+                    }
+                    return Ok(());
+                }
+                match sender.try_send(Some((frame, sequence))) {
+                    Err(TrySendError::Full(_)) => {
+                        // TODO keep track of these frames?
+                        log::info!("dropping frame {}", ts);
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        log::info!("frame receiver disconnected");
+                    }
+                    _ => {}
+                }
+                Ok(())
+            },
+        ),
+    )?;
+    Ok(receiver)
+}
+
+/// Get the interop factory used to create capture items for windows and monitors. Fails with
+/// `Error::CaptureNotSupported` if it is not available.
+pub(crate) fn capture_item_interop() -> Result<IGraphicsCaptureItemInterop> {
+    factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>().map_err(|e| {
+        log::warn!("GraphicsCaptureItem interop is not available: {}", e);
+        Error::CaptureNotSupported
+    })
+}
+
+/// Convert `SystemRelativeTime`, which is measured in 100ns units, to `Duration`.
+fn system_relative_time_to_duration(ticks: i64) -> Duration {
+    Duration::from_nanos(ticks.max(0) as u64 * 100)
+}
+
+fn get_dxgi_interface_from_object<S: Interface, R: Interface>(
+    object: &S,
+) -> windows::core::Result<R> {
     let access: IDirect3DDxgiInterfaceAccess = object.cast()?;
     let object = unsafe { access.GetInterface::<R>()? };
     Ok(object)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> RectInt32 {
+        RectInt32 {
+            X: x,
+            Y: y,
+            Width: width,
+            Height: height,
+        }
+    }
+
+    fn make_box(left: u32, top: u32, right: u32, bottom: u32) -> D3D11_BOX {
+        D3D11_BOX {
+            left,
+            top,
+            right,
+            bottom,
+            front: 0,
+            back: 1,
+        }
+    }
+
+    #[test]
+    fn bounding_box_of_dirty_regions() {
+        let dirty_box = bounding_box(vec![rect(10, 20, 5, 5), rect(-3, 40, 10, 10)]);
+        assert_eq!(dirty_box, make_box(0, 20, 15, 50));
+    }
+
+    #[test]
+    fn bounding_box_without_dirty_regions_is_empty() {
+        assert_eq!(bounding_box(vec![]), make_box(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn full_copy_after_dropped_frames() {
+        // the first frame has nothing to be relative to
+        assert!(!is_contiguous(None, 0));
+        assert!(is_contiguous(Some(4), 5));
+        // frames 5 and 6 were dropped by the frame handler
+        assert!(!is_contiguous(Some(4), 7));
+        assert!(!is_contiguous(Some(u64::MAX), 0));
+    }
+
+    #[test]
+    fn max_fps_must_be_positive_and_finite() {
+        assert!(is_valid_max_fps(30.0));
+        assert!(is_valid_max_fps(0.5));
+        for fps in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(!is_valid_max_fps(fps));
+        }
+    }
+
+    #[test]
+    fn clamp_box_exceeding_texture() {
+        let clamped = clamp_box(make_box(10, 20, 300, 400), 100, 200);
+        assert_eq!(clamped, make_box(10, 20, 100, 200));
+    }
+
+    #[test]
+    fn clamp_box_outside_texture_is_empty() {
+        let clamped = clamp_box(make_box(150, 250, 300, 400), 100, 200);
+        assert_eq!(clamped, make_box(100, 200, 100, 200));
+        assert!(is_empty_box(&clamped));
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use windows::{
+    Graphics::DirectX::DirectXPixelFormat,
+    Win32::{
+        Foundation::RECT,
+        Graphics::Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+        UI::WindowsAndMessaging::{
+            GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN,
+            SM_YVIRTUALSCREEN,
+        },
+    },
+};
+
+use crate::{
+    frame::{bytes_per_pixel, OwnedFrame},
+    Result,
+};
+
+use super::{display::Display, Capture, CaptureBuilder, MaybeFrame, OutputFormat};
+
+/// How often displays are polled if their refresh rate is unknown.
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// The virtual desktop, i.e. the bounding rectangle of all monitors.
+///
+/// Windows.Graphics.Capture can't capture the virtual desktop as a whole, so it doesn't implement
+/// `Capturable`. Use `VirtualDesktopCapture` to capture all displays into a single frame.
+pub struct VirtualDesktop;
+
+impl VirtualDesktop {
+    /// Get the bounds of the virtual desktop in screen coordinates. Note that the origin is
+    /// negative if there are monitors to the left of or above the primary monitor.
+    pub fn bounds() -> RECT {
+        unsafe {
+            let left = GetSystemMetrics(SM_XVIRTUALSCREEN);
+            let top = GetSystemMetrics(SM_YVIRTUALSCREEN);
+            RECT {
+                left,
+                top,
+                right: left + GetSystemMetrics(SM_CXVIRTUALSCREEN),
+                bottom: top + GetSystemMetrics(SM_CYVIRTUALSCREEN),
+            }
+        }
+    }
+
+    /// Get the (width, height) of the virtual desktop.
+    pub fn size() -> (u32, u32) {
+        let bounds = Self::bounds();
+        (
+            (bounds.right - bounds.left) as u32,
+            (bounds.bottom - bounds.top) as u32,
+        )
+    }
+}
+
+/// Captures all displays and composites their frames into a single frame covering the virtual
+/// desktop. Areas of the virtual desktop not covered by any display are black.
+///
+/// Every display is captured by its own `Capture`, and frames are composited in CPU memory, so
+/// this is considerably slower than capturing a single display.
+pub struct VirtualDesktopCapture {
+    captures: Vec<(Capture, (u32, u32))>,
+    canvas: OwnedFrame,
+}
+
+impl VirtualDesktopCapture {
+    /// Create captures for all displays. `configure` is applied to the builder of every capture;
+    /// CPU access, the `B8G8R8A8UIntNormalized` pixel format and `OutputFormat::Native` are always
+    /// enabled, since frames are composited as BGRA.
+    pub fn new(configure: impl Fn(CaptureBuilder) -> CaptureBuilder) -> Result<Self> {
+        let bounds = VirtualDesktop::bounds();
+        let (width, height) = VirtualDesktop::size();
+        let bpp = bytes_per_pixel(DXGI_FORMAT_B8G8R8A8_UNORM) as usize;
+        let mut captures = Vec::new();
+        for display in Display::enumerate()? {
+            let monitor = display.display_info.monitorInfo.rcMonitor;
+            let offset = (
+                (monitor.left - bounds.left) as u32,
+                (monitor.top - bounds.top) as u32,
+            );
+            let capture = configure(CaptureBuilder::new(Box::new(display)))
+                .set_cpu_access(true)
+                .set_pixel_format(DirectXPixelFormat::B8G8R8A8UIntNormalized)
+                .set_output_format(OutputFormat::Native)
+                .build()?;
+            captures.push((capture, offset));
+        }
+        Ok(Self {
+            captures,
+            canvas: OwnedFrame {
+                width,
+                height,
+                format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                timestamp: Default::default(),
+                cursor: None,
+                sequence: 0,
+                data: vec![0; width as usize * height as usize * bpp],
+            },
+        })
+    }
+
+    /// Get the (width, height) of the composited frames.
+    pub fn content_size(&self) -> (u32, u32) {
+        (self.canvas.width, self.canvas.height)
+    }
+
+    /// Get the captures of individual displays.
+    pub fn captures(&mut self) -> impl Iterator<Item = &mut Capture> {
+        self.captures.iter_mut().map(|(capture, _)| capture)
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        for capture in self.captures() {
+            capture.start()?;
+        }
+        Ok(())
+    }
+
+    /// Wait until at least one of the displays produces a new frame, and get the composited frame.
+    /// Displays are polled at the shortest of their `Capture::frame_interval_hint()`s.
+    ///
+    /// Returns `None` if capturing has ended for all displays.
+    pub fn grab(&mut self) -> Result<Option<&OwnedFrame>> {
+        let poll_interval = self
+            .captures
+            .iter()
+            .filter_map(|(capture, _)| capture.frame_interval_hint())
+            .min()
+            .unwrap_or(POLL_INTERVAL);
+        let bpp = bytes_per_pixel(self.canvas.format) as usize;
+        loop {
+            let mut is_updated = false;
+            let mut is_active = false;
+            for (capture, (x, y)) in self.captures.iter_mut() {
+                match capture.try_grab()? {
+                    MaybeFrame::Some(frame) => {
+                        is_active = true;
+                        is_updated = true;
+                        self.canvas.timestamp = self.canvas.timestamp.max(frame.timestamp);
+                        let desc = frame.desc();
+                        if desc.Format != self.canvas.format {
+                            log::warn!("skipping display frame of format {:?}", desc.Format);
+                            continue;
+                        }
+                        // the frame is composited before the next one is grabbed
+                        let Some(data) = (unsafe { frame.as_slice() }) else {
+                            continue;
+                        };
+                        let canvas_row_size = self.canvas.width as usize * bpp;
+                        let row_size =
+                            (desc.Width.min(self.canvas.width.saturating_sub(*x)) as usize) * bpp;
+                        let rows = data
+                            .chunks(frame.row_pitch() as usize)
+                            .take(self.canvas.height.saturating_sub(*y) as usize);
+                        for (i, row) in rows.enumerate() {
+                            let start = (*y as usize + i) * canvas_row_size + *x as usize * bpp;
+                            self.canvas.data[start..start + row_size]
+                                .copy_from_slice(&row[..row_size]);
+                        }
+                    }
+                    MaybeFrame::Pending | MaybeFrame::Minimized => is_active = true,
+                    MaybeFrame::None => {}
+                }
+            }
+            if is_updated {
+                // composited frames are numbered on their own, display frames arrive independently
+                self.canvas.sequence += 1;
+                return Ok(Some(&self.canvas));
+            }
+            if !is_active {
+                return Ok(None);
+            }
+            std::thread::sleep(poll_interval);
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        for capture in self.captures() {
+            if !capture.is_stopped() {
+                capture.stop()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -2,42 +2,89 @@ use std::{
     collections::HashMap,
     sync::{
         mpsc::{sync_channel, Receiver, SyncSender},
-        RwLock,
+        Mutex, RwLock,
     },
+    time::{Duration, Instant},
 };
 
 use once_cell::sync::Lazy;
 use windows::{
-    core::Result,
     Graphics::Capture::GraphicsCaptureItem,
     Win32::{
-        Foundation::{BOOL, HWND, LPARAM, POINT, RECT},
+        Foundation::{BOOL, E_FAIL, E_INVALIDARG, HWND, LPARAM, POINT, RECT},
         Graphics::{
             Direct3D11::D3D11_BOX,
-            Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED, DWM_CLOAKED_SHELL},
-            Gdi::ClientToScreen,
+            Dwm::{
+                DwmGetWindowAttribute, DWMWA_CLOAKED, DWMWA_EXTENDED_FRAME_BOUNDS,
+                DWM_CLOAKED_SHELL,
+            },
+            Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+            Gdi::{
+                ClientToScreen, CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject,
+                GetDC, RedrawWindow, ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB,
+                DIB_RGB_COLORS, RDW_ALLCHILDREN, RDW_FRAME, RDW_INVALIDATE,
+            },
         },
+        Storage::Xps::{PrintWindow, PRINT_WINDOW_FLAGS},
         System::{
-            Console::GetConsoleWindow, WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
+            Console::GetConsoleWindow,
+            Threading::{AttachThreadInput, GetCurrentThreadId},
         },
         UI::{
             Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK},
             WindowsAndMessaging::{
-                EnumWindows, GetAncestor, GetClassNameW, GetClientRect, GetShellWindow,
-                GetWindowLongW, GetWindowRect, GetWindowTextW, GetWindowThreadProcessId,
-                IsWindowVisible, EVENT_OBJECT_DESTROY, GA_ROOT, GWL_EXSTYLE, GWL_STYLE,
-                WINEVENT_OUTOFCONTEXT, WS_DISABLED, WS_EX_TOOLWINDOW,
+                BringWindowToTop, EnumWindows, GetAncestor, GetClassNameW, GetClientRect,
+                GetForegroundWindow, GetShellWindow, GetWindowLongW, GetWindowPlacement,
+                GetWindowRect, GetWindowTextW, GetWindowThreadProcessId, IsChild, IsIconic,
+                IsWindow, IsWindowVisible, SetForegroundWindow, ShowWindow, WindowFromPoint,
+                EVENT_OBJECT_DESTROY, GA_ROOT, GWL_EXSTYLE, GWL_STYLE, PW_RENDERFULLCONTENT,
+                SW_RESTORE, WINDOWPLACEMENT, WINEVENT_OUTOFCONTEXT, WS_DISABLED, WS_EX_TOOLWINDOW,
             },
         },
     },
 };
 
-use crate::util::convert_u16_string;
+use crate::{frame::OwnedFrame, Error, Result};
+
+use crate::util::convert_u16_string_with_len;
+
+use super::{capture_item_interop, display::Display, Capturable};
 
-use super::Capturable;
+/// Close notification channels keyed by the hook id, along with the handle of the window each of
+/// them is waiting for.
+type CloseSenders = HashMap<isize, (isize, SyncSender<()>)>;
 
-static OBJECT_DESTROYED_USER_DATA: Lazy<RwLock<HashMap<isize, (isize, SyncSender<()>)>>> =
-    Lazy::new(Default::default);
+static OBJECT_DESTROYED_USER_DATA: Lazy<RwLock<CloseSenders>> = Lazy::new(Default::default);
+
+fn insert_close_sender(
+    senders: &mut CloseSenders,
+    hook_id: isize,
+    window_handle: isize,
+    sender: SyncSender<()>,
+) {
+    senders.insert(hook_id, (window_handle, sender));
+}
+
+/// Notify the channel registered for `hook_id` if it is waiting for `window_handle`. Returns
+/// whether it has been notified.
+fn notify_close_sender(senders: &CloseSenders, hook_id: isize, window_handle: isize) -> bool {
+    match senders.get(&hook_id) {
+        Some((handle, tx)) if *handle == window_handle => {
+            tx.send(()).ok();
+            true
+        }
+        _ => false,
+    }
+}
+
+fn remove_close_sender(senders: &mut CloseSenders, hook_id: isize) -> bool {
+    senders.remove(&hook_id).is_some()
+}
+
+/// Check whether a window style (as returned by `GetWindowLongW`) has `flag` set.
+fn has_flag(style: i32, flag: u32) -> bool {
+    style as u32 & flag != 0
+}
 
 extern "system" fn object_destroyed_cb(
     this: HWINEVENTHOOK,
@@ -48,18 +95,13 @@ extern "system" fn object_destroyed_cb(
     _: u32,
     _: u32,
 ) {
+    // `handle` is compared with the exact handle of the tracked window, so destroyed child windows
+    // (which have handles of their own) never match. checking `GetAncestor(handle, GA_ROOT)`
+    // instead wouldn't work: with an out-of-context hook, the event is delivered after the window
+    // is gone, when `GetAncestor` returns null
     if id_object == 0 && id_child == 0 && handle != HWND::default() {
         let has_been_closed = if let Ok(handles) = OBJECT_DESTROYED_USER_DATA.read() {
-            if let Some((window_handle, tx)) = handles.get(&(this.0 as isize)) {
-                if *window_handle == handle.0 as isize {
-                    tx.send(()).ok();
-                    true
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
+            notify_close_sender(&handles, this.0 as isize, handle.0 as isize)
         } else {
             // TODO is that correct?
             true
@@ -73,23 +115,35 @@ extern "system" fn object_destroyed_cb(
     }
 }
 
+struct EnumWindowsState {
+    windows: Vec<Window>,
+    capturable_only: bool,
+}
+
 extern "system" fn enum_windows_cb(window: HWND, state: LPARAM) -> BOOL {
+    let state = unsafe { Box::leak(Box::from_raw(state.0 as *mut EnumWindowsState)) };
     let window_info = Window::new(window);
-    if window_info.is_capturable() {
-        let state = unsafe { Box::leak(Box::from_raw(state.0 as *mut Vec<Window>)) };
-        state.push(window_info);
+    if !state.capturable_only || window_info.is_capturable() {
+        state.windows.push(window_info);
     }
     true.into()
 }
 
-fn enumerate_capturable_windows() -> Vec<Window> {
-    let state = Box::into_raw(Box::default());
-    *unsafe {
+fn enumerate_windows(capturable_only: bool) -> Vec<Window> {
+    let state = Box::into_raw(Box::new(EnumWindowsState {
+        windows: Vec::new(),
+        capturable_only,
+    }));
+    unsafe {
         EnumWindows(Some(enum_windows_cb), LPARAM(state as isize)).expect("EnumWindows");
-        Box::from_raw(state)
+        Box::from_raw(state).windows
     }
 }
 
+fn enumerate_capturable_windows() -> Vec<Window> {
+    enumerate_windows(true)
+}
+
 fn find_window_by_name(window_name: &str) -> Vec<Window> {
     let mut found: Vec<Window> = Vec::new();
     let name_lower = window_name.to_lowercase();
@@ -103,26 +157,61 @@ fn find_window_by_name(window_name: &str) -> Vec<Window> {
 
 fn get_window_text(handle: HWND) -> String {
     let mut title = [0u16; 512];
-    // TODO: check errors
-    unsafe { GetWindowTextW(handle, &mut title) };
-    convert_u16_string(&title)
+    let len = unsafe { GetWindowTextW(handle, &mut title) };
+    convert_u16_string_with_len(&title, len)
 }
 
 fn get_window_class_name(handle: HWND) -> String {
     let mut class_name = [0u16; 512];
-    // TODO: check errors
-    unsafe { GetClassNameW(handle, &mut class_name) };
-    convert_u16_string(&class_name)
+    let len = unsafe { GetClassNameW(handle, &mut class_name) };
+    convert_u16_string_with_len(&class_name, len)
 }
 
-#[derive(Clone, Debug)]
+/// The reason a window is not capturable, see `Window::is_capturable_detailed`.
+#[derive(thiserror::Error, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapturableRejection {
+    #[error("the window is not visible")]
+    NotVisible,
+    #[error("the window is the shell (desktop) window")]
+    ShellWindow,
+    #[error("the window is the console window of this process")]
+    ConsoleWindow,
+    #[error("the window is not a top-level window")]
+    NotTopLevel,
+    #[error("the window is disabled")]
+    Disabled,
+    #[error("the window is a tool window (e.g. a tooltip)")]
+    Tooltip,
+    #[error("the window is a system window (taskbar, desktop, Task View or a XAML popup)")]
+    KnownBlocked,
+    #[error("the window is a cloaked UWP window")]
+    CloakedUwp,
+}
+
+#[derive(Debug)]
 pub struct Window {
     pub handle: HWND,
     pub title: String,
     pub class_name: String,
+    // close notification hooks registered by this instance
+    close_hooks: Mutex<Vec<HWINEVENTHOOK>>,
+}
+
+impl Clone for Window {
+    fn clone(&self) -> Self {
+        // hooks belong to the captures created from this instance, so they are not shared
+        Self {
+            handle: self.handle,
+            title: self.title.clone(),
+            class_name: self.class_name.clone(),
+            close_hooks: Default::default(),
+        }
+    }
 }
 
 impl Window {
+    /// Create a `Window` for the given handle. This does not check whether the window is
+    /// capturable, see `is_capturable()`.
     pub fn new(handle: HWND) -> Self {
         let title = get_window_text(handle);
         let class_name = get_window_class_name(handle);
@@ -130,11 +219,78 @@ impl Window {
             handle,
             title,
             class_name,
+            close_hooks: Default::default(),
+        }
+    }
+
+    /// Re-read the title and class name from the window, as they might change during its lifetime.
+    pub fn refresh(&mut self) {
+        self.title = get_window_text(self.handle);
+        self.class_name = get_window_class_name(self.handle);
+    }
+
+    /// Get all top-level windows, including ones which are not capturable (e.g. tool windows).
+    pub fn enumerate_all() -> Vec<Window> {
+        enumerate_windows(false)
+    }
+
+    pub fn find_first(window_name: &str) -> Result<Window> {
+        find_window_by_name(window_name)
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::WindowNotFound(window_name.to_string()))
+    }
+
+    /// Like `find_first`, but keeps polling until a capturable window with the given name appears
+    /// or `timeout` elapses. Useful right after launching a process, when its window might not
+    /// exist or be visible yet.
+    pub fn wait_for(window_name: &str, timeout: Duration) -> Result<Window> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(50);
+        let deadline = Instant::now() + timeout;
+        loop {
+            match Self::find_first(window_name) {
+                Err(Error::WindowNotFound(_)) if Instant::now() < deadline => {
+                    std::thread::sleep(POLL_INTERVAL.min(deadline - Instant::now()));
+                }
+                result => return result,
+            }
         }
     }
 
-    pub fn find_first(window_name: &str) -> Option<Window> {
-        find_window_by_name(window_name).into_iter().next()
+    /// Find capturable top-level windows belonging to the process with the given id.
+    ///
+    /// Windows are returned in the order they are enumerated by `EnumWindows`.
+    pub fn find_by_process_id(process_id: u32) -> Vec<Window> {
+        enumerate_capturable_windows()
+            .into_iter()
+            .filter(|window| window.get_process_id() == process_id)
+            .collect()
+    }
+
+    /// Find capturable top-level windows with the given class name.
+    ///
+    /// Matching is case-sensitive, as are class names in Win32.
+    pub fn find_by_class_name(class_name: &str) -> Vec<Window> {
+        enumerate_capturable_windows()
+            .into_iter()
+            .filter(|window| window.class_name == class_name)
+            .collect()
+    }
+
+    /// Find a capturable top-level window located at the given point in screen coordinates.
+    ///
+    /// Returns `None` if there is no window at that point, or if it's not capturable (e.g. the
+    /// desktop or the taskbar).
+    pub fn from_point(x: i32, y: i32) -> Option<Window> {
+        let handle = unsafe { WindowFromPoint(POINT { x, y }) };
+        if handle.is_invalid() {
+            return None;
+        }
+        let window = Window::new(unsafe { GetAncestor(handle, GA_ROOT) });
+        if window.handle.is_invalid() || !window.is_capturable() {
+            return None;
+        }
+        Some(window)
     }
 
     pub fn matches_title_and_class_name(&self, title: &str, class_name: &str) -> bool {
@@ -147,7 +303,11 @@ impl Window {
         // XAML Islands
         self.matches_title_and_class_name("DesktopWindowXamlSource", "Windows.UI.Core.CoreWindow") ||
         // XAML Popups
-        self.matches_title_and_class_name("PopupHost", "Xaml_WindowedPopupClass")
+        self.matches_title_and_class_name("PopupHost", "Xaml_WindowedPopupClass") ||
+        // Taskbar
+        self.class_name == "Shell_TrayWnd" ||
+        // Desktop
+        self.class_name == "Progman" || self.class_name == "WorkerW"
     }
 
     pub fn is_visible(&self) -> bool {
@@ -181,11 +341,11 @@ impl Window {
     }
 
     pub fn is_disabled(&self) -> bool {
-        self.get_style() & (WS_DISABLED.0 as i32) == 1
+        has_flag(self.get_style(), WS_DISABLED.0)
     }
 
     pub fn is_tooltip(&self) -> bool {
-        self.get_ex_style() & (WS_EX_TOOLWINDOW.0 as i32) == 1
+        has_flag(self.get_ex_style(), WS_EX_TOOLWINDOW.0)
     }
 
     pub fn is_uwp_window(&self) -> bool {
@@ -206,24 +366,217 @@ impl Window {
         dwm_attr_cloaked.is_ok() && cloaked == DWM_CLOAKED_SHELL
     }
 
+    /// Whether this window can be captured. Note that windows behind other windows are capturable
+    /// as well, but might not produce new frames while occluded, see `Capture::force_redraw`.
     pub fn is_capturable(&self) -> bool {
-        if !self.is_visible()
-            || self.is_shell_window()
-            || self.is_console_window()
-            || !self.is_top_level()
-            || self.is_disabled()
-            || self.is_tooltip()
-            || self.is_known_blocked_window()
-        {
-            return false;
-        }
+        self.is_capturable_detailed().is_ok()
+    }
 
+    /// Same as `is_capturable()`, but tells why the window can't be captured.
+    pub fn is_capturable_detailed(&self) -> std::result::Result<(), CapturableRejection> {
+        if !self.is_visible() {
+            return Err(CapturableRejection::NotVisible);
+        }
+        if self.is_shell_window() {
+            return Err(CapturableRejection::ShellWindow);
+        }
+        if self.is_console_window() {
+            return Err(CapturableRejection::ConsoleWindow);
+        }
+        if !self.is_top_level() {
+            return Err(CapturableRejection::NotTopLevel);
+        }
+        if self.is_disabled() {
+            return Err(CapturableRejection::Disabled);
+        }
+        if self.is_tooltip() {
+            return Err(CapturableRejection::Tooltip);
+        }
+        if self.is_known_blocked_window() {
+            return Err(CapturableRejection::KnownBlocked);
+        }
         // Check to see if the self is cloaked if it's a UWP
         if self.is_uwp_window() && self.is_dwm_cloaked() {
-            return false;
+            return Err(CapturableRejection::CloakedUwp);
+        }
+        Ok(())
+    }
+
+    /// Get the bounds of the window frame as rendered by DWM, in screen coordinates.
+    ///
+    /// Unlike `GetWindowRect`, this excludes the invisible resize borders which are not present
+    /// in the captured texture. Falls back to `GetWindowRect` if DWM can't provide the bounds.
+    pub fn get_frame_bounds(&self) -> Result<RECT> {
+        let mut rect = RECT::default();
+        let result = unsafe {
+            DwmGetWindowAttribute(
+                self.handle,
+                DWMWA_EXTENDED_FRAME_BOUNDS,
+                &mut rect as *mut _ as *mut _,
+                std::mem::size_of::<RECT>() as u32,
+            )
+        };
+        if result.is_err() {
+            unsafe { GetWindowRect(self.handle, &mut rect)? };
+        }
+        Ok(rect)
+    }
+
+    /// Get the window rectangle in screen coordinates (`GetWindowRect`), including the invisible
+    /// resize borders. See `get_frame_bounds` for the visible part of the window.
+    pub fn window_rect(&self) -> Result<RECT> {
+        let mut rect = RECT::default();
+        unsafe { GetWindowRect(self.handle, &mut rect)? };
+        Ok(rect)
+    }
+
+    /// Get the client area rectangle (`GetClientRect`). Its origin is always (0, 0), see
+    /// `client_origin_on_screen` for the position of the client area.
+    pub fn client_rect(&self) -> Result<RECT> {
+        let mut rect = RECT::default();
+        unsafe { GetClientRect(self.handle, &mut rect)? };
+        Ok(rect)
+    }
+
+    /// Get the position of the top-left corner of the client area in screen coordinates.
+    pub fn client_origin_on_screen(&self) -> Result<POINT> {
+        let mut point = POINT::default();
+        unsafe { ClientToScreen(self.handle, &mut point).ok()? };
+        Ok(point)
+    }
+
+    /// Capture a child window (e.g. a single control) of this window. Windows.Graphics.Capture
+    /// can only capture top-level windows, so this window is captured instead and frames are
+    /// cropped to the rectangle of the child.
+    ///
+    /// Returns an error if `child` is not a descendant of this window.
+    pub fn capture_child(&self, child: HWND) -> Result<ChildWindow> {
+        if !unsafe { IsChild(self.handle, child) }.as_bool() {
+            return Err(windows::core::Error::new(
+                E_INVALIDARG,
+                format!("window {:?} is not a child of '{}'", child.0, self.title),
+            )
+            .into());
+        }
+        Ok(ChildWindow {
+            parent: self.clone(),
+            child,
+        })
+    }
+
+    /// Restore this window if it is minimized, so that it renders (and produces frames) again.
+    /// Does nothing for windows which are not minimized.
+    pub fn restore(&self) -> Result<()> {
+        if unsafe { IsIconic(self.handle) }.as_bool() {
+            // the return value is the previous visibility, not an error
+            let _ = unsafe { ShowWindow(self.handle, SW_RESTORE) };
+        }
+        Ok(())
+    }
+
+    /// Bring this window to the foreground and activate it, e.g. to make sure it renders before
+    /// capturing. Minimized windows should be restored with `restore()` first.
+    ///
+    /// Windows only lets a process change the foreground window under certain conditions (e.g.
+    /// when it has received the last input event), otherwise the taskbar button just flashes. To
+    /// work around this, the input of the calling thread is temporarily attached to the thread
+    /// owning the current foreground window. This is still not guaranteed to succeed, e.g. while
+    /// the foreground lock is held by another application or a menu is open - an error is
+    /// returned in that case.
+    pub fn set_foreground(&self) -> Result<()> {
+        let current_thread = unsafe { GetCurrentThreadId() };
+        let foreground_thread = unsafe { GetWindowThreadProcessId(GetForegroundWindow(), None) };
+        let attached = foreground_thread != 0
+            && foreground_thread != current_thread
+            && unsafe { AttachThreadInput(current_thread, foreground_thread, true) }.as_bool();
+        let activated = unsafe { SetForegroundWindow(self.handle) }.as_bool();
+        let _ = unsafe { BringWindowToTop(self.handle) };
+        if attached {
+            let _ = unsafe { AttachThreadInput(current_thread, foreground_thread, false) };
+        }
+        if !activated {
+            return Err(windows::core::Error::new(
+                E_FAIL,
+                format!("failed to bring '{}' to the foreground", self.title),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Take a one-shot snapshot of this window using `PrintWindow`, which unlike
+    /// Windows.Graphics.Capture also works while the window is minimized. This is not a stream:
+    /// use it as a fallback for `Capture::grab()` when `Capture::target_ready()` is `false`.
+    ///
+    /// The snapshot covers the whole window (including the title bar) at its restored size, with
+    /// the `B8G8R8A8UIntNormalized` pixel format. Its timestamp is zero and it has no cursor info.
+    /// This is best-effort - windows which don't render while minimized (or don't support
+    /// `PrintWindow` at all) produce a partially or fully black image.
+    pub fn thumbnail(&self) -> Result<OwnedFrame> {
+        let rect = if unsafe { IsIconic(self.handle) }.as_bool() {
+            let mut placement = WINDOWPLACEMENT {
+                length: std::mem::size_of::<WINDOWPLACEMENT>() as u32,
+                ..Default::default()
+            };
+            unsafe { GetWindowPlacement(self.handle, &mut placement)? };
+            placement.rcNormalPosition
+        } else {
+            self.window_rect()?
+        };
+        let (width, height) = (rect.right - rect.left, rect.bottom - rect.top);
+        if width <= 0 || height <= 0 {
+            return Err(windows::core::Error::new(E_INVALIDARG, "window has zero size").into());
+        }
+
+        let bitmap_info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: width,
+                // negative height makes the bitmap top-down, like frames
+                biHeight: -height,
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = unsafe {
+            let screen_dc = GetDC(HWND::default());
+            let dc = CreateCompatibleDC(screen_dc);
+            let mut bits = std::ptr::null_mut();
+            let result = CreateDIBSection(dc, &bitmap_info, DIB_RGB_COLORS, &mut bits, None, 0)
+                .and_then(|bitmap| {
+                    let previous = SelectObject(dc, bitmap);
+                    let printed =
+                        PrintWindow(self.handle, dc, PRINT_WINDOW_FLAGS(PW_RENDERFULLCONTENT));
+                    let data = std::slice::from_raw_parts(
+                        bits as *const u8,
+                        width as usize * height as usize * 4,
+                    )
+                    .to_vec();
+                    SelectObject(dc, previous);
+                    let _ = DeleteObject(bitmap);
+                    printed.ok().map(|()| data)
+                });
+            let _ = DeleteDC(dc);
+            ReleaseDC(HWND::default(), screen_dc);
+            result?
+        };
+        // GDI leaves the alpha channel undefined
+        for pixel in data.chunks_exact_mut(4) {
+            pixel[3] = 255;
         }
 
-        true
+        Ok(OwnedFrame {
+            width: width as u32,
+            height: height as u32,
+            format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            timestamp: Duration::ZERO,
+            cursor: None,
+            sequence: 0,
+            data,
+        })
     }
 
     pub fn get_process_id(&self) -> u32 {
@@ -232,6 +585,8 @@ impl Window {
         process_id
     }
 
+    /// Print diagnostic information about this window to stdout. This is meant for command-line
+    /// tools only - apart from this method, the crate only reports through the `log` facade.
     pub fn print_info(&self) {
         println!("title = {}", self.title);
         println!("class = {}", self.class_name);
@@ -253,28 +608,38 @@ impl Window {
 
 impl Capturable for Window {
     fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
-        let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
-        unsafe { interop.CreateForWindow(self.handle) }
+        let interop = capture_item_interop()?;
+        unsafe { interop.CreateForWindow(self.handle) }.map_err(|e| {
+            if e.code() != E_INVALIDARG {
+                return e.into();
+            }
+            // CreateForWindow only reports E_INVALIDARG, so try to guess the actual reason
+            let reason = if !unsafe { IsWindow(self.handle).as_bool() } {
+                "the window handle is invalid, the window may have been closed"
+            } else if !self.is_top_level() {
+                "only top-level windows can be captured, try the parent window"
+            } else {
+                "the window may be a child, overlay or shell window; try the parent window"
+            };
+            Error::WindowNotCapturable {
+                title: self.title.clone(),
+                class_name: self.class_name.clone(),
+                reason: reason.to_string(),
+            }
+        })
     }
 
     fn get_client_box(&self) -> Result<D3D11_BOX> {
-        let mut window_rect = RECT::default();
-        let mut client_rect = RECT::default();
-        let mut top_left = POINT::default();
-        unsafe {
-            GetWindowRect(self.handle, &mut window_rect)?;
-            let _ = ClientToScreen(self.handle, &mut top_left);
-            GetClientRect(self.handle, &mut client_rect)?;
-        }
+        let window_rect = self.get_frame_bounds()?;
+        let client_rect = self.client_rect()?;
+        let top_left = self.client_origin_on_screen()?;
 
+        // the texture produced by Windows.Graphics.Capture starts at the top-left corner of the
+        // window frame as it is rendered by DWM, so the client area is offset by the frame size
         let mut client_box = D3D11_BOX::default();
-        // TODO
-        // 1 seems to work because most window have a 1-pixel gap in the D3D11 texture
-        // produced by Windows.Graphics.Capture. Why tho?
-        client_box.left = 1;
+        client_box.left = (top_left.x - window_rect.left).max(0) as u32;
         client_box.right = client_box.left + (client_rect.right - client_rect.left) as u32;
-        // TODO there seems to be no reliadble way of getting the taskbar height, so this code is fairly brittle
-        client_box.top = (top_left.y - window_rect.top) as u32;
+        client_box.top = (top_left.y - window_rect.top).max(0) as u32;
         client_box.bottom = client_box.top + (client_rect.bottom - client_rect.top) as u32;
         client_box.front = 0;
         client_box.back = 1;
@@ -283,28 +648,322 @@ impl Capturable for Window {
 
     fn get_close_notification_channel(&self) -> Receiver<()> {
         let (sender, receiver) = sync_channel(1);
+        let mut process_id = 0u32;
+        let thread_id = unsafe { GetWindowThreadProcessId(self.handle, Some(&mut process_id)) };
         let hook_id = unsafe {
+            // scope the hook to the thread owning the window, so that we don't have to process
+            // destroy events from the whole system. this hook does not catch all the closes
+            // (e.g. explorer windows), which is why `is_closed` is also polled by `Capture`
             SetWinEventHook(
                 EVENT_OBJECT_DESTROY,
                 EVENT_OBJECT_DESTROY,
                 None,
                 Some(object_destroyed_cb),
-                // TODO filtering by process id does not always catch the moment when the window is closed
-                // why? aren't windows bound to their process ids?
-                // moreover, for explorer windows even that does not work.
-                // need some more realiable and simpler way to track window closing
-                0,
-                0,
+                process_id,
+                thread_id,
                 WINEVENT_OUTOFCONTEXT,
             )
         };
         if let Ok(mut handles) = OBJECT_DESTROYED_USER_DATA.write() {
-            handles.insert(hook_id.0 as isize, (self.handle.0 as isize, sender));
+            insert_close_sender(
+                &mut handles,
+                hook_id.0 as isize,
+                self.handle.0 as isize,
+                sender,
+            );
+        }
+        if let Ok(mut hooks) = self.close_hooks.lock() {
+            hooks.push(hook_id);
         }
         receiver
     }
 
+    fn release_close_notification_channels(&self) {
+        let Ok(mut hooks) = self.close_hooks.lock() else {
+            return;
+        };
+        let mut handles = OBJECT_DESTROYED_USER_DATA.write().ok();
+        for hook_id in hooks.drain(..) {
+            // removing the entry first makes sure the callback doesn't try to unhook it again
+            if let Some(handles) = handles.as_mut() {
+                remove_close_sender(handles, hook_id.0 as isize);
+            }
+            unsafe {
+                let _ = UnhookWinEvent(hook_id);
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        !unsafe { IsWindow(self.handle) }.as_bool()
+    }
+
+    fn is_minimized(&self) -> bool {
+        unsafe { IsIconic(self.handle) }.as_bool()
+    }
+
+    fn is_ready(&self) -> bool {
+        !self.is_minimized()
+    }
+
+    fn refresh(&mut self) {
+        Window::refresh(self);
+    }
+
     fn get_raw_handle(&self) -> isize {
         self.handle.0 as isize
     }
+
+    fn get_screen_origin(&self) -> Option<(i32, i32)> {
+        let bounds = self.get_frame_bounds().ok()?;
+        Some((bounds.left, bounds.top))
+    }
+
+    fn get_refresh_rate(&self) -> Option<u32> {
+        Display::from_window(self).ok()?.refresh_rate()
+    }
+
+    fn request_redraw(&self) -> Result<()> {
+        // no RDW_UPDATENOW: painting synchronously would block on a window which is not responding
+        unsafe {
+            RedrawWindow(
+                self.handle,
+                None,
+                None,
+                RDW_INVALIDATE | RDW_FRAME | RDW_ALLCHILDREN,
+            )
+        }
+        .ok()?;
+        Ok(())
+    }
+}
+
+/// A child window captured through its parent window, see `Window::capture_child`.
+///
+/// The capture box follows the child when the parent window is resized, but not when only the
+/// child moves within the parent.
+pub struct ChildWindow {
+    parent: Window,
+    child: HWND,
+}
+
+impl ChildWindow {
+    /// Get the top-level window which is actually captured.
+    pub fn parent(&self) -> &Window {
+        &self.parent
+    }
+
+    pub fn handle(&self) -> HWND {
+        self.child
+    }
+}
+
+impl Capturable for ChildWindow {
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
+        self.parent.create_capture_item()
+    }
+
+    fn get_client_box(&self) -> Result<D3D11_BOX> {
+        let window_rect = self.parent.get_frame_bounds()?;
+        let mut child_rect = RECT::default();
+        unsafe { GetWindowRect(self.child, &mut child_rect)? };
+
+        // same as for `Window`, the texture starts at the top-left corner of the parent's frame
+        let left = (child_rect.left - window_rect.left).max(0) as u32;
+        let top = (child_rect.top - window_rect.top).max(0) as u32;
+        Ok(D3D11_BOX {
+            left,
+            top,
+            right: ((child_rect.right - window_rect.left).max(0) as u32).max(left),
+            bottom: ((child_rect.bottom - window_rect.top).max(0) as u32).max(top),
+            front: 0,
+            back: 1,
+        })
+    }
+
+    fn get_close_notification_channel(&self) -> Receiver<()> {
+        self.parent.get_close_notification_channel()
+    }
+
+    fn release_close_notification_channels(&self) {
+        self.parent.release_close_notification_channels();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.parent.is_closed() || !unsafe { IsWindow(self.child) }.as_bool()
+    }
+
+    fn is_minimized(&self) -> bool {
+        self.parent.is_minimized()
+    }
+
+    fn is_ready(&self) -> bool {
+        self.parent.is_ready()
+    }
+
+    fn refresh(&mut self) {
+        self.parent.refresh();
+    }
+
+    fn get_raw_handle(&self) -> isize {
+        self.child.0 as isize
+    }
+
+    fn get_screen_origin(&self) -> Option<(i32, i32)> {
+        self.parent.get_screen_origin()
+    }
+
+    fn get_refresh_rate(&self) -> Option<u32> {
+        self.parent.get_refresh_rate()
+    }
+
+    fn request_redraw(&self) -> Result<()> {
+        self.parent.request_redraw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::UI::WindowsAndMessaging::WS_VISIBLE;
+
+    #[test]
+    fn style_flag_set() {
+        let style = (WS_DISABLED | WS_VISIBLE).0 as i32;
+        assert!(has_flag(style, WS_DISABLED.0));
+        assert!(has_flag(WS_EX_TOOLWINDOW.0 as i32, WS_EX_TOOLWINDOW.0));
+    }
+
+    #[test]
+    fn style_flag_clear() {
+        assert!(!has_flag(WS_VISIBLE.0 as i32, WS_DISABLED.0));
+        assert!(!has_flag(0, WS_EX_TOOLWINDOW.0));
+    }
+
+    #[test]
+    fn close_sender_is_notified_for_its_window() {
+        let mut senders = CloseSenders::new();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        insert_close_sender(&mut senders, 1, 100, tx);
+        // another window destroyed while the hook is active
+        assert!(!notify_close_sender(&senders, 1, 200));
+        assert!(rx.try_recv().is_err());
+        assert!(notify_close_sender(&senders, 1, 100));
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn removed_close_sender_is_not_notified() {
+        let mut senders = CloseSenders::new();
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        insert_close_sender(&mut senders, 1, 100, tx);
+        assert!(remove_close_sender(&mut senders, 1));
+        assert!(!remove_close_sender(&mut senders, 1));
+        assert!(senders.is_empty());
+        assert!(!notify_close_sender(&senders, 1, 100));
+        // the sender has been dropped along with the entry
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn close_sender_removal_is_keyed_by_hook() {
+        let mut senders = CloseSenders::new();
+        let (tx1, _rx1) = std::sync::mpsc::sync_channel(1);
+        let (tx2, rx2) = std::sync::mpsc::sync_channel(1);
+        // two captures of the same window
+        insert_close_sender(&mut senders, 1, 100, tx1);
+        insert_close_sender(&mut senders, 2, 100, tx2);
+        assert!(remove_close_sender(&mut senders, 1));
+        assert_eq!(senders.len(), 1);
+        assert!(notify_close_sender(&senders, 2, 100));
+        assert!(rx2.try_recv().is_ok());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[ignore = "requires a desktop session with a capturable window"]
+    fn stopped_captures_release_close_senders() {
+        crate::init().unwrap();
+        let window = Window::enumerate_all()
+            .into_iter()
+            .find(Window::is_capturable)
+            .expect("no capturable window found");
+        for _ in 0..100 {
+            let mut capture = crate::CaptureBuilder::new(Box::new(Window::new(window.handle)))
+                .build()
+                .unwrap();
+            capture.start().unwrap();
+            capture.stop().unwrap();
+        }
+        assert!(OBJECT_DESTROYED_USER_DATA.read().unwrap().is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[ignore = "requires a desktop session"]
+    fn captured_size_matches_client_rect() {
+        use std::time::{Duration, Instant};
+        use windows::{
+            core::w,
+            Win32::{
+                Foundation::HINSTANCE,
+                UI::WindowsAndMessaging::{
+                    CreateWindowExW, DestroyWindow, DispatchMessageW, PeekMessageW,
+                    TranslateMessage, HMENU, MSG, PM_REMOVE, WINDOW_EX_STYLE, WS_OVERLAPPEDWINDOW,
+                },
+            },
+        };
+
+        crate::init().unwrap();
+        let handle = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                w!("STATIC"),
+                w!("zbl test"),
+                WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+                100,
+                100,
+                640,
+                480,
+                HWND::default(),
+                HMENU::default(),
+                HINSTANCE::default(),
+                None,
+            )
+        }
+        .unwrap();
+        let mut client_rect = RECT::default();
+        unsafe { GetClientRect(handle, &mut client_rect) }.unwrap();
+
+        let mut capture = crate::CaptureBuilder::new(Box::new(Window::new(handle)))
+            .build()
+            .unwrap();
+        capture.start().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let size = loop {
+            // the window has to process its messages to get painted
+            let mut msg = MSG::default();
+            while unsafe { PeekMessageW(&mut msg, HWND::default(), 0, 0, PM_REMOVE) }.as_bool() {
+                unsafe {
+                    let _ = TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+            if let crate::MaybeFrame::Some(frame) = capture.try_grab().unwrap() {
+                break (frame.width(), frame.height());
+            }
+            assert!(Instant::now() < deadline, "no frame has been captured");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        capture.stop().unwrap();
+        unsafe { DestroyWindow(handle) }.unwrap();
+
+        assert_eq!(
+            size,
+            (
+                (client_rect.right - client_rect.left) as u32,
+                (client_rect.bottom - client_rect.top) as u32
+            )
+        );
+    }
 }
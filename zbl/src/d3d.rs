@@ -1,19 +1,31 @@
+use std::mem::ManuallyDrop;
+
 use windows::{
-    core::{Interface, Result},
+    core::{Error, Interface, Result},
     Graphics::DirectX::Direct3D11::IDirect3DDevice,
     Win32::{
+        Foundation::RECT,
         Graphics::{
             Direct3D::{D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP},
             Direct3D11::{
                 D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Resource,
-                ID3D11Texture2D, D3D11_BIND_FLAG, D3D11_BIND_SHADER_RESOURCE, D3D11_BOX,
-                D3D11_CPU_ACCESS_FLAG, D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT,
-                D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG,
-                D3D11_RESOURCE_MISC_SHARED, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC,
-                D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+                ID3D11Texture2D, ID3D11VideoContext, ID3D11VideoDevice, ID3D11VideoProcessor,
+                ID3D11VideoProcessorEnumerator, D3D11_BIND_FLAG, D3D11_BIND_RENDER_TARGET,
+                D3D11_BIND_SHADER_RESOURCE, D3D11_BOX, D3D11_CPU_ACCESS_FLAG,
+                D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_MAPPED_SUBRESOURCE,
+                D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG, D3D11_RESOURCE_MISC_SHARED,
+                D3D11_RESOURCE_MISC_SHARED_NTHANDLE, D3D11_SDK_VERSION, D3D11_TEX2D_VPIV,
+                D3D11_TEX2D_VPOV, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+                D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE, D3D11_VIDEO_PROCESSOR_COLOR_SPACE,
+                D3D11_VIDEO_PROCESSOR_CONTENT_DESC, D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC,
+                D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0,
+                D3D11_VIDEO_PROCESSOR_NOMINAL_RANGE_16_235, D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC,
+                D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_STREAM,
+                D3D11_VIDEO_USAGE_OPTIMAL_SPEED, D3D11_VPIV_DIMENSION_TEXTURE2D,
+                D3D11_VPOV_DIMENSION_TEXTURE2D,
             },
             Dxgi::{
-                Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+                Common::{DXGI_FORMAT, DXGI_FORMAT_NV12, DXGI_RATIONAL, DXGI_SAMPLE_DESC},
                 IDXGIDevice, DXGI_ERROR_UNSUPPORTED,
             },
         },
@@ -21,8 +33,13 @@ use windows::{
     },
 };
 
-fn create_d3d_device() -> Result<ID3D11Device> {
-    for driver_type in [D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP] {
+fn create_d3d_device(warp: bool) -> Result<ID3D11Device> {
+    let driver_types: &[_] = if warp {
+        &[D3D_DRIVER_TYPE_WARP]
+    } else {
+        &[D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP]
+    };
+    for &driver_type in driver_types {
         let mut device = None;
         let result = unsafe {
             D3D11CreateDevice(
@@ -44,8 +61,10 @@ fn create_d3d_device() -> Result<ID3D11Device> {
         };
     }
 
-    // TODO result
-    panic!("failed to create D3D device with any of the supported driver types");
+    Err(Error::new(
+        DXGI_ERROR_UNSUPPORTED,
+        "failed to create D3D device with any of the supported driver types",
+    ))
 }
 
 fn create_direct3d_device(d3d_device: &ID3D11Device) -> Result<IDirect3DDevice> {
@@ -54,6 +73,19 @@ fn create_direct3d_device(d3d_device: &ID3D11Device) -> Result<IDirect3DDevice>
     inspectable.cast()
 }
 
+/// Sharing flags of textures created by `D3D::create_texture` without CPU access.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureSharing {
+    /// No sharing flags. Some interop APIs only accept such textures.
+    None,
+    /// `D3D11_RESOURCE_MISC_SHARED`, the texture can be opened via a legacy shared handle.
+    #[default]
+    Shared,
+    /// `D3D11_RESOURCE_MISC_SHARED | D3D11_RESOURCE_MISC_SHARED_NTHANDLE`, the texture can be
+    /// shared via an NT handle (see `Frame::shared_handle()`).
+    SharedNtHandle,
+}
+
 pub struct D3D {
     pub device: ID3D11Device,
     pub context: ID3D11DeviceContext,
@@ -61,8 +93,10 @@ pub struct D3D {
 }
 
 impl D3D {
-    pub fn new() -> Result<Self> {
-        let device = create_d3d_device()?;
+    /// Create a D3D11 device. Hardware devices are preferred, unless `warp` is set, in which case
+    /// the WARP software renderer is always used.
+    pub fn new(warp: bool) -> Result<Self> {
+        let device = create_d3d_device(warp)?;
         let context = unsafe { device.GetImmediateContext()? };
         let direct3d_device = create_direct3d_device(&device)?;
         Ok(Self {
@@ -81,6 +115,7 @@ impl D3D {
         height: u32,
         format: DXGI_FORMAT,
         cpu_access: bool,
+        sharing: TextureSharing,
     ) -> Result<ID3D11Texture2D> {
         let usage = if cpu_access {
             D3D11_USAGE_STAGING
@@ -100,10 +135,14 @@ impl D3D {
             D3D11_BIND_SHADER_RESOURCE //| D3D11_BIND_RENDER_TARGET
         };
 
-        let misc_flags = if cpu_access {
-            D3D11_RESOURCE_MISC_FLAG(0)
-        } else {
-            D3D11_RESOURCE_MISC_SHARED //| D3D11_RESOURCE_MISC_SHARED_NTHANDLE
+        let misc_flags = match sharing {
+            _ if cpu_access => D3D11_RESOURCE_MISC_FLAG(0),
+            TextureSharing::None => D3D11_RESOURCE_MISC_FLAG(0),
+            TextureSharing::Shared => D3D11_RESOURCE_MISC_SHARED,
+            // NTHANDLE can only be combined with SHARED or SHARED_KEYEDMUTEX
+            TextureSharing::SharedNtHandle => {
+                D3D11_RESOURCE_MISC_SHARED | D3D11_RESOURCE_MISC_SHARED_NTHANDLE
+            }
         };
 
         let desc = D3D11_TEXTURE2D_DESC {
@@ -166,14 +205,28 @@ impl D3D {
         src: &ID3D11Texture2D,
         dst: &ID3D11Texture2D,
         region: &D3D11_BOX,
+    ) -> Result<()> {
+        self.copy_texture_to(src, dst, region, 0, 0)
+    }
+
+    /**
+     * Copy the region of src texture to dst, placing it at (dst_x, dst_y).
+     */
+    pub fn copy_texture_to(
+        &self,
+        src: &ID3D11Texture2D,
+        dst: &ID3D11Texture2D,
+        region: &D3D11_BOX,
+        dst_x: u32,
+        dst_y: u32,
     ) -> Result<()> {
         unsafe {
             // https://learn.microsoft.com/en-us/windows/win32/api/d3d11/nf-d3d11-id3d11devicecontext-copysubresourceregion
             self.context.CopySubresourceRegion(
                 Some(&dst.cast()?),
                 0,
-                0,
-                0,
+                dst_x,
+                dst_y,
                 0,
                 Some(&src.cast()?),
                 0,
@@ -183,3 +236,172 @@ impl D3D {
         Ok(())
     }
 }
+
+/// Scales textures and converts their format on the GPU using the D3D11 video processor.
+pub struct Scaler {
+    video_device: ID3D11VideoDevice,
+    video_context: ID3D11VideoContext,
+    enumerator: ID3D11VideoProcessorEnumerator,
+    processor: ID3D11VideoProcessor,
+    output: ID3D11Texture2D,
+    output_size: (u32, u32),
+}
+
+impl Scaler {
+    /**
+     * Create a scaler from input of the given size to output of the given size and format.
+     * NV12 output uses BT.709 studio range, as expected by video encoders.
+     */
+    pub fn new(
+        d3d: &D3D,
+        input_size: (u32, u32),
+        output_size: (u32, u32),
+        format: DXGI_FORMAT,
+    ) -> Result<Self> {
+        let video_device: ID3D11VideoDevice = d3d.device.cast()?;
+        let video_context: ID3D11VideoContext = d3d.context.cast()?;
+        let content_desc = D3D11_VIDEO_PROCESSOR_CONTENT_DESC {
+            InputFrameFormat: D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE,
+            InputFrameRate: DXGI_RATIONAL {
+                Numerator: 60,
+                Denominator: 1,
+            },
+            InputWidth: input_size.0,
+            InputHeight: input_size.1,
+            OutputFrameRate: DXGI_RATIONAL {
+                Numerator: 60,
+                Denominator: 1,
+            },
+            OutputWidth: output_size.0,
+            OutputHeight: output_size.1,
+            Usage: D3D11_VIDEO_USAGE_OPTIMAL_SPEED,
+        };
+        let (enumerator, processor) = unsafe {
+            // https://learn.microsoft.com/en-us/windows/win32/api/d3d11/nf-d3d11-id3d11videodevice-createvideoprocessor
+            let enumerator = video_device.CreateVideoProcessorEnumerator(&content_desc)?;
+            let processor = video_device.CreateVideoProcessor(&enumerator, 0)?;
+            video_context.VideoProcessorSetStreamFrameFormat(
+                &processor,
+                0,
+                D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE,
+            );
+            // we want plain scaling, without any color adjustments
+            video_context.VideoProcessorSetStreamAutoProcessingMode(&processor, 0, false);
+            if format == DXGI_FORMAT_NV12 {
+                video_context.VideoProcessorSetOutputColorSpace(
+                    &processor,
+                    &D3D11_VIDEO_PROCESSOR_COLOR_SPACE {
+                        // YCbCr_Matrix = BT.709, Nominal_Range = 16-235
+                        _bitfield: (1 << 2)
+                            | ((D3D11_VIDEO_PROCESSOR_NOMINAL_RANGE_16_235.0 as u32) << 4),
+                    },
+                );
+            }
+            (enumerator, processor)
+        };
+
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: output_size.0,
+            Height: output_size.1,
+            Format: format,
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            BindFlags: (D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE).0 as u32,
+            MiscFlags: 0,
+            Usage: D3D11_USAGE_DEFAULT,
+            CPUAccessFlags: 0,
+        };
+        let mut output = None;
+        unsafe {
+            d3d.device.CreateTexture2D(&desc, None, Some(&mut output))?;
+        }
+
+        Ok(Self {
+            video_device,
+            video_context,
+            enumerator,
+            processor,
+            output: output.expect("CreateTexture2D returned nullptr instead of texture"),
+            output_size,
+        })
+    }
+
+    /**
+     * Get the (width, height) of the output texture.
+     */
+    pub fn output_size(&self) -> (u32, u32) {
+        self.output_size
+    }
+
+    /**
+     * Scale the region of src texture to the output texture.
+     */
+    pub fn scale(&self, src: &ID3D11Texture2D, region: &D3D11_BOX) -> Result<&ID3D11Texture2D> {
+        let input_view_desc = D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC {
+            FourCC: 0,
+            ViewDimension: D3D11_VPIV_DIMENSION_TEXTURE2D,
+            Anonymous: D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0 {
+                Texture2D: D3D11_TEX2D_VPIV {
+                    MipSlice: 0,
+                    ArraySlice: 0,
+                },
+            },
+        };
+        let output_view_desc = D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC {
+            ViewDimension: D3D11_VPOV_DIMENSION_TEXTURE2D,
+            Anonymous: D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0 {
+                Texture2D: D3D11_TEX2D_VPOV { MipSlice: 0 },
+            },
+        };
+        let source_rect = RECT {
+            left: region.left as i32,
+            top: region.top as i32,
+            right: region.right as i32,
+            bottom: region.bottom as i32,
+        };
+
+        unsafe {
+            let mut input_view = None;
+            self.video_device.CreateVideoProcessorInputView(
+                &src.cast::<ID3D11Resource>()?,
+                &self.enumerator,
+                &input_view_desc,
+                Some(&mut input_view),
+            )?;
+            let mut output_view = None;
+            self.video_device.CreateVideoProcessorOutputView(
+                &self.output.cast::<ID3D11Resource>()?,
+                &self.enumerator,
+                &output_view_desc,
+                Some(&mut output_view),
+            )?;
+
+            self.video_context.VideoProcessorSetStreamSourceRect(
+                &self.processor,
+                0,
+                true,
+                Some(&source_rect),
+            );
+            let mut stream = D3D11_VIDEO_PROCESSOR_STREAM {
+                Enable: true.into(),
+                pInputSurface: ManuallyDrop::new(input_view),
+                ..Default::default()
+            };
+            // https://learn.microsoft.com/en-us/windows/win32/api/d3d11/nf-d3d11-id3d11videocontext-videoprocessorblt
+            let result = self.video_context.VideoProcessorBlt(
+                &self.processor,
+                output_view.as_ref(),
+                0,
+                std::slice::from_ref(&stream),
+            );
+            ManuallyDrop::drop(&mut stream.pInputSurface);
+            result?;
+        }
+
+        Ok(&self.output)
+    }
+}
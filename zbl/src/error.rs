@@ -0,0 +1,30 @@
+/// Errors returned by this crate.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("window not found: {0}")]
+    WindowNotFound(String),
+    #[error(
+        "display index {index} is out of range, {count} display(s) available (valid ids are 0..{count})"
+    )]
+    DisplayIndexOutOfRange { index: usize, count: usize },
+    #[error("window \"{title}\" (class \"{class_name}\") cannot be captured: {reason}")]
+    WindowNotCapturable {
+        title: String,
+        class_name: String,
+        reason: String,
+    },
+    #[error("D3D device has been lost ({0}), the capture has to be recreated")]
+    DeviceLost(windows::core::Error),
+    #[error("capture target has been closed before a frame was captured")]
+    NoFrameCaptured,
+    #[error("capture has not been started, call `start()` first")]
+    NotStarted,
+    #[error("Windows Graphics Capture is not supported on this system")]
+    CaptureNotSupported,
+    #[error("windows api error: {0}")]
+    Windows(#[from] windows::core::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,10 +1,122 @@
-use windows::Win32::Graphics::Direct3D11::{
-    ID3D11Texture2D, D3D11_MAPPED_SUBRESOURCE, D3D11_TEXTURE2D_DESC,
+use windows::{
+    core::{Error, Interface, PCWSTR},
+    Win32::{
+        Foundation::{E_INVALIDARG, HANDLE},
+        Graphics::{
+            Direct3D11::{
+                ID3D11Texture2D, D3D11_BOX, D3D11_MAPPED_SUBRESOURCE,
+                D3D11_RESOURCE_MISC_SHARED_NTHANDLE, D3D11_TEXTURE2D_DESC,
+            },
+            Dxgi::{
+                Common::{
+                    DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_B8G8R8A8_UNORM_SRGB,
+                    DXGI_FORMAT_NV12, DXGI_FORMAT_R10G10B10A2_UNORM,
+                    DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R8G8B8A8_UNORM,
+                    DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+                },
+                IDXGIResource1, DXGI_SHARED_RESOURCE_READ,
+            },
+        },
+    },
 };
 
+use std::{io::Write, time::Duration};
+
+use crate::Result;
+
+/// A frame with its pixels copied into CPU memory as tightly packed rows. Unlike `Frame`, this
+/// does not reference any GPU resources, stays valid after subsequent grabs, and can be sent
+/// between threads.
+#[derive(Clone, Debug)]
+pub struct OwnedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub format: DXGI_FORMAT,
+    /// See `Frame::timestamp`.
+    pub timestamp: Duration,
+    /// See `Frame::cursor`.
+    pub cursor: Option<CursorInfo>,
+    /// See `Frame::sequence`.
+    pub sequence: u64,
+    pub data: Vec<u8>,
+}
+
+/// Position and visibility of the mouse cursor, see `CaptureBuilder::set_capture_cursor_metadata`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CursorInfo {
+    /// Position of the cursor hotspot in frame coordinates.
+    pub x: i32,
+    pub y: i32,
+    /// Whether the cursor is shown (it is hidden e.g. while typing, or by the application).
+    pub visible: bool,
+}
+
+/// Get the number of bytes used by a single pixel of the given format.
+pub fn bytes_per_pixel(format: DXGI_FORMAT) -> u32 {
+    match format {
+        DXGI_FORMAT_R16G16B16A16_FLOAT => 8,
+        DXGI_FORMAT_B8G8R8A8_UNORM
+        | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+        | DXGI_FORMAT_R8G8B8A8_UNORM
+        | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        | DXGI_FORMAT_R10G10B10A2_UNORM => 4,
+        // luma plane, see `row_count`
+        DXGI_FORMAT_NV12 => 1,
+        // Windows.Graphics.Capture only produces the formats above
+        format => {
+            log::warn!(
+                "unexpected frame format {:?}, assuming 4 bytes per pixel",
+                format
+            );
+            4
+        }
+    }
+}
+
+/// Get the number of rows of the given height occupied by all planes of a frame. NV12 frames have a
+/// full-size luma (Y) plane followed by a half-height plane of interleaved chroma (UV) samples,
+/// so their rows are `Width` bytes each, and there are `Height * 3 / 2` of them.
+pub fn row_count(format: DXGI_FORMAT, height: u32) -> u32 {
+    match format {
+        DXGI_FORMAT_NV12 => height + height / 2,
+        _ => height,
+    }
+}
+
+/// Get the size of the tightly packed pixel data of a frame, see `Frame::to_vec()`.
+pub fn packed_len(format: DXGI_FORMAT, width: u32, height: u32) -> usize {
+    width as usize * row_count(format, height) as usize * bytes_per_pixel(format) as usize
+}
+
+/// Iterate over the rows of mapped frame data with the row padding stripped, see `Frame::rows()`.
+fn packed_rows(
+    data: &[u8],
+    format: DXGI_FORMAT,
+    width: u32,
+    height: u32,
+    row_pitch: u32,
+) -> impl Iterator<Item = &[u8]> {
+    let row_size = width as usize * bytes_per_pixel(format) as usize;
+    data.chunks(row_pitch as usize)
+        .take(row_count(format, height) as usize)
+        .map(move |row| &row[..row_size])
+}
+
 pub struct Frame {
     pub texture: ID3D11Texture2D,
     pub mapped_ptr: D3D11_MAPPED_SUBRESOURCE,
+    /// The region of this frame that has changed since the previous frame, in frame coordinates.
+    /// Only set when capturing with `CaptureBuilder::set_dirty_region_only(true)`.
+    pub dirty_rect: Option<D3D11_BOX>,
+    /// The time this frame was captured at, relative to system boot (`SystemRelativeTime`).
+    pub timestamp: Duration,
+    /// The mouse cursor at the time the frame was grabbed. Only set when capturing with
+    /// `CaptureBuilder::set_capture_cursor_metadata(true)` and the cursor is within the frame.
+    pub cursor: Option<CursorInfo>,
+    /// The number of frames that have arrived from the capture session before this one. Frames
+    /// dropped because they were not grabbed in time (or skipped due to `max_fps`) are counted
+    /// too, so a jump in this number means frames have been missed.
+    pub sequence: u64,
 }
 
 impl Frame {
@@ -12,6 +124,10 @@ impl Frame {
         Self {
             texture,
             mapped_ptr,
+            dirty_rect: None,
+            timestamp: Duration::ZERO,
+            cursor: None,
+            sequence: 0,
         }
     }
 
@@ -24,4 +140,249 @@ impl Frame {
         unsafe { self.texture.GetDesc(&mut desc) };
         desc
     }
+
+    pub fn width(&self) -> u32 {
+        self.desc().Width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.desc().Height
+    }
+
+    /// Get the number of bytes between the starts of consecutive rows of the mapped data. Returns
+    /// 0 if the frame was produced without CPU access.
+    pub fn row_pitch(&self) -> u32 {
+        self.mapped_ptr.RowPitch
+    }
+
+    /// Get the pixel format of this frame.
+    pub fn format(&self) -> DXGI_FORMAT {
+        self.desc().Format
+    }
+
+    /// Get the number of bytes used by a single pixel of this frame.
+    pub fn bytes_per_pixel(&self) -> u32 {
+        bytes_per_pixel(self.format())
+    }
+
+    /// Get the raw mapped bytes of this frame, including row padding (`RowPitch * Height` bytes,
+    /// or `RowPitch * Height * 3 / 2` for NV12 frames, see `nv12_planes()`).
+    ///
+    /// Returns `None` if the frame was produced without CPU access.
+    ///
+    /// # Safety
+    ///
+    /// The slice points into the staging texture, which is reused between frames: the next frame
+    /// grabbed from the same `Capture` overwrites it. The slice must not be used after that, use
+    /// `to_vec()` or `to_owned()` to keep the pixels around.
+    pub unsafe fn as_slice(&self) -> Option<&[u8]> {
+        if self.mapped_ptr.pData.is_null() {
+            return None;
+        }
+        let desc = self.desc();
+        let len = self.row_pitch() as usize * row_count(desc.Format, desc.Height) as usize;
+        Some(std::slice::from_raw_parts(
+            self.mapped_ptr.pData as *const u8,
+            len,
+        ))
+    }
+
+    /// Get the luma (Y) and interleaved chroma (UV) planes of an NV12 frame (see
+    /// `CaptureBuilder::set_output_format`), including row padding. Both planes have rows of
+    /// `row_pitch()` bytes, the UV plane has half as many rows.
+    ///
+    /// Returns `None` if this is not an NV12 frame, or if it was produced without CPU access.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `as_slice()`.
+    pub unsafe fn nv12_planes(&self) -> Option<(&[u8], &[u8])> {
+        let desc = self.desc();
+        if desc.Format != DXGI_FORMAT_NV12 {
+            return None;
+        }
+        Some(
+            self.as_slice()?
+                .split_at(self.row_pitch() as usize * desc.Height as usize),
+        )
+    }
+
+    /// Copy the pixels of this frame into a tightly packed buffer of
+    /// `Width * Height * bytes_per_pixel()` bytes, i.e. with the row padding stripped. NV12 frames
+    /// are packed the same way, with the UV plane following the Y plane.
+    ///
+    /// Returns `None` if the frame was produced without CPU access. The copy contains pixels of a
+    /// later frame if it is made after the next frame is grabbed from the same `Capture`.
+    pub fn to_vec(&self) -> Option<Vec<u8>> {
+        // the rows are copied right away and don't outlive this call
+        let rows = unsafe { self.rows() }?;
+        let mut buf = Vec::with_capacity(self.packed_len());
+        for row in rows {
+            buf.extend_from_slice(row);
+        }
+        Some(buf)
+    }
+
+    /// Write the pixels of this frame to `writer` in the same tightly packed layout as `to_vec()`,
+    /// without an intermediate buffer.
+    ///
+    /// Returns an error if the frame was produced without CPU access.
+    pub fn write_packed<W: Write>(&self, writer: &mut W) -> Result<()> {
+        // the rows are written right away and don't outlive this call
+        let rows = unsafe { self.rows() }.ok_or_else(|| {
+            Error::new(
+                E_INVALIDARG,
+                "cannot copy a frame without CPU access, use `set_cpu_access(true)`",
+            )
+        })?;
+        for row in rows {
+            writer.write_all(row)?;
+        }
+        Ok(())
+    }
+
+    /// Get the size of the tightly packed pixel data of this frame, see `to_vec()`.
+    pub fn packed_len(&self) -> usize {
+        let desc = self.desc();
+        packed_len(desc.Format, desc.Width, desc.Height)
+    }
+
+    /// Iterate over the rows of this frame with the row padding stripped, i.e. over
+    /// `Width * bytes_per_pixel()`-byte slices of the mapped data. This is the same data as
+    /// `to_vec()`, but without allocating a buffer for the whole frame. NV12 frames yield the rows
+    /// of the Y plane followed by the rows of the UV plane.
+    ///
+    /// Returns `None` if the frame was produced without CPU access.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `as_slice()`.
+    pub unsafe fn rows(&self) -> Option<impl Iterator<Item = &[u8]>> {
+        let data = self.as_slice()?;
+        let desc = self.desc();
+        Some(packed_rows(
+            data,
+            desc.Format,
+            desc.Width,
+            desc.Height,
+            self.row_pitch(),
+        ))
+    }
+
+    /// Copy the pixels and metadata of this frame into an `OwnedFrame`, which stays valid after
+    /// subsequent grabs.
+    ///
+    /// Returns an error if the frame was produced without CPU access.
+    pub fn to_owned(&self) -> Result<OwnedFrame> {
+        let data = self.to_vec().ok_or_else(|| {
+            Error::new(
+                E_INVALIDARG,
+                "cannot copy a frame without CPU access, use `set_cpu_access(true)`",
+            )
+        })?;
+        let desc = self.desc();
+        Ok(OwnedFrame {
+            width: desc.Width,
+            height: desc.Height,
+            format: desc.Format,
+            timestamp: self.timestamp,
+            cursor: self.cursor,
+            sequence: self.sequence,
+            data,
+        })
+    }
+
+    /// Create an NT handle to the texture of this frame, which can be opened on another device or
+    /// in another process (e.g. with `ID3D11Device1::OpenSharedResource1`).
+    ///
+    /// This requires the capture to be created with `CaptureBuilder::set_shared(true)` and
+    /// without CPU access. The caller owns the returned handle and must close it with
+    /// `CloseHandle`. Note that the texture is reused between frames, so its contents are only
+    /// valid until the next `grab()`.
+    pub fn shared_handle(&self) -> Result<HANDLE> {
+        if self.desc().MiscFlags & D3D11_RESOURCE_MISC_SHARED_NTHANDLE.0 as u32 == 0 {
+            return Err(Error::new(
+                E_INVALIDARG,
+                "frame texture is not shareable, use `set_shared(true)` and `set_cpu_access(false)`",
+            ).into());
+        }
+        let resource: IDXGIResource1 = self.texture.cast()?;
+        Ok(unsafe {
+            resource.CreateSharedHandle(None, DXGI_SHARED_RESOURCE_READ.0, PCWSTR::null())?
+        })
+    }
+}
+
+/// Save a CPU-accessible frame as a PNG image.
+///
+/// Returns an error if the frame was produced without CPU access, or is not in 8-bit BGRA format.
+#[cfg(feature = "png")]
+pub fn save_frame_png(frame: &Frame, path: &std::path::Path) -> Result<()> {
+    frame.to_owned()?.save_png(path)
+}
+
+impl OwnedFrame {
+    /// Get the number of bytes used by a single pixel of this frame.
+    pub fn bytes_per_pixel(&self) -> u32 {
+        bytes_per_pixel(self.format)
+    }
+
+    /// Save this frame as a PNG image.
+    ///
+    /// Returns an error if the frame is not in 8-bit BGRA format.
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, path: &std::path::Path) -> Result<()> {
+        use windows::Win32::Foundation::E_FAIL;
+
+        if self.format != DXGI_FORMAT_B8G8R8A8_UNORM
+            && self.format != DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+        {
+            return Err(Error::new(E_FAIL, "only 8-bit BGRA frames can be saved as PNG").into());
+        }
+        // BGRA -> RGBA
+        let mut data = self.data.clone();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let to_error = |e: png::EncodingError| Error::new(E_FAIL, e.to_string());
+        let mut writer = encoder.write_header().map_err(to_error)?;
+        writer.write_image_data(&data).map_err(to_error)?;
+        writer.finish().map_err(to_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_rows_strip_padding() {
+        // 3x2 BGRA frame with rows padded to 16 bytes
+        let data: Vec<u8> = (0..32).collect();
+        let rows: Vec<_> = packed_rows(&data, DXGI_FORMAT_B8G8R8A8_UNORM, 3, 2, 16).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], &data[0..12]);
+        assert_eq!(rows[1], &data[16..28]);
+        assert_eq!(
+            rows.concat().len(),
+            packed_len(DXGI_FORMAT_B8G8R8A8_UNORM, 3, 2)
+        );
+    }
+
+    #[test]
+    fn packed_rows_of_nv12_include_uv_plane() {
+        // 4x4 NV12 frame: 4 rows of Y and 2 rows of interleaved UV, padded to 8 bytes
+        let data: Vec<u8> = (0..48).collect();
+        let rows: Vec<_> = packed_rows(&data, DXGI_FORMAT_NV12, 4, 4, 8).collect();
+        assert_eq!(rows.len(), 6);
+        assert!(rows.iter().all(|row| row.len() == 4));
+        assert_eq!(rows[4], &data[32..36]);
+        assert_eq!(rows.concat().len(), packed_len(DXGI_FORMAT_NV12, 4, 4));
+    }
 }
@@ -1,37 +1,90 @@
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod capture;
 pub mod d3d;
+pub mod error;
 pub mod frame;
+pub mod recorder;
 pub mod util;
+#[cfg(feature = "record")]
+pub mod video;
 
-pub use capture::{display::Display, window::Window, Capturable, Capture, CaptureBuilder};
-pub use frame::Frame;
+#[cfg(feature = "tokio")]
+pub use capture::async_capture::AsyncCapture;
+pub use capture::{
+    channel::{CaptureHandle, FramePump},
+    display::{Display, HdrInfo},
+    item::{pick_capture_item, RawCaptureItem},
+    session_capabilities, snapshot,
+    virtual_desktop::{VirtualDesktop, VirtualDesktopCapture},
+    window::{CapturableRejection, ChildWindow, Window},
+    Capturable, Capture, CaptureBuilder, CloseReason, FramePolicy, MaybeFrame, OutputFormat,
+    SessionCapabilities,
+};
+pub use d3d::TextureSharing;
+pub use error::{Error, Result};
+#[cfg(feature = "png")]
+pub use frame::save_frame_png;
+pub use frame::{CursorInfo, Frame, OwnedFrame};
+pub use recorder::{SegmentFormat, SegmentedRecorder};
+#[cfg(feature = "record")]
+pub use video::Recorder;
 
 // re-export winapi
 pub use windows;
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use windows::Win32::{
-    System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED},
-    UI::HiDpi::{SetProcessDpiAwareness, PROCESS_PER_MONITOR_DPI_AWARE},
+use once_cell::sync::OnceCell;
+use windows::{
+    core::HRESULT,
+    Win32::{
+        Foundation::{E_ACCESSDENIED, RPC_E_CHANGED_MODE, S_OK},
+        System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED},
+        UI::HiDpi::{SetProcessDpiAwareness, PROCESS_PER_MONITOR_DPI_AWARE},
+    },
 };
 
-pub fn init() {
-    ro_initialize_once();
-    set_dpi_aware();
+/// Initialize WinRT and make the process DPI-aware. This should be called before creating any
+/// captures, and can safely be called multiple times.
+///
+/// Returns an error if WinRT could not be initialized, in which case no capture will work.
+pub fn init() -> Result<()> {
+    init_with(true)
 }
 
-pub fn ro_initialize_once() {
-    static mut STATE: AtomicBool = AtomicBool::new(false);
-    unsafe {
-        let state = STATE.swap(true, Ordering::SeqCst);
-        if !state {
-            RoInitialize(RO_INIT_MULTITHREADED).ok();
-        }
-    };
+/// Like `init()`, but only makes the process DPI-aware if `dpi_aware` is set. Pass `false` when
+/// the host application manages DPI awareness itself.
+///
+/// Note that in a DPI-unaware process, window sizes and coordinates are virtualized on high-DPI
+/// displays, so client areas of windows might be cropped incorrectly.
+pub fn init_with(dpi_aware: bool) -> Result<()> {
+    ro_initialize_once()?;
+    if dpi_aware {
+        set_dpi_aware()?;
+    }
+    Ok(())
+}
+
+/// Initialize WinRT once, returning the result of the first initialization on subsequent calls.
+pub fn ro_initialize_once() -> Result<()> {
+    static STATE: OnceCell<HRESULT> = OnceCell::new();
+    let code = *STATE.get_or_init(|| match unsafe { RoInitialize(RO_INIT_MULTITHREADED) } {
+        Ok(()) => S_OK,
+        Err(e) => e.code(),
+    });
+    // RPC_E_CHANGED_MODE means the thread is already initialized as single-threaded, which
+    // works just as well for capturing
+    if code.is_ok() || code == RPC_E_CHANGED_MODE {
+        Ok(())
+    } else {
+        Err(windows::core::Error::from_hresult(code).into())
+    }
 }
 
-pub fn set_dpi_aware() {
-    unsafe {
-        SetProcessDpiAwareness(PROCESS_PER_MONITOR_DPI_AWARE).ok();
+/// Make the process per-monitor DPI-aware. Does nothing if the DPI awareness has already been set
+/// (e.g. by the application manifest).
+pub fn set_dpi_aware() -> Result<()> {
+    match unsafe { SetProcessDpiAwareness(PROCESS_PER_MONITOR_DPI_AWARE) } {
+        Err(e) if e.code() != E_ACCESSDENIED => Err(e.into()),
+        _ => Ok(()),
     }
 }
@@ -0,0 +1,313 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::Error,
+    Win32::{Foundation::E_INVALIDARG, Graphics::Dxgi::Common::DXGI_FORMAT},
+};
+
+use crate::Result;
+
+use crate::{capture::Capture, frame::Frame};
+
+/// Callback invoked with the index and the path of a finished segment.
+pub type RolloverCallback = Box<dyn FnMut(usize, &Path)>;
+
+/// Format of the segments written by `SegmentedRecorder`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SegmentFormat {
+    /// Each segment is a `<prefix>_<index>.raw` file containing a plain concatenation of
+    /// tightly-packed frames (see `Frame::to_vec`).
+    #[default]
+    Raw,
+    /// Each segment is a `<prefix>_<index>` directory containing one `<frame>.png` image per
+    /// frame. Only 8-bit BGRA frames can be recorded this way.
+    #[cfg(feature = "png")]
+    Png,
+}
+
+/// Records frames from a `Capture` into a sequence of segments, rolling over to a new segment
+/// every N frames and/or every M seconds, and whenever the size or the format of frames changes.
+///
+/// Every segment has a `<prefix>_<index>.csv` index next to it, with a
+/// `frame,timestamp_ns,width,height,dxgi_format,location` line per frame. `location` is the byte
+/// offset of the frame in the `.raw` file, or the file name of the image in the PNG directory.
+///
+/// The capture must be created with CPU access enabled.
+pub struct SegmentedRecorder {
+    capture: Capture,
+    output_dir: PathBuf,
+    prefix: String,
+    format: SegmentFormat,
+    max_frames_per_segment: Option<usize>,
+    max_segment_duration: Option<Duration>,
+    on_rollover: Option<RolloverCallback>,
+    segment_index: usize,
+    segment: Option<Segment>,
+}
+
+/// A segment which is being written to.
+struct Segment {
+    /// The `.raw` file or the PNG directory.
+    path: PathBuf,
+    // `None` for PNG segments, whose frames are written to files of their own
+    writer: Option<BufWriter<File>>,
+    index: BufWriter<File>,
+    frames: usize,
+    bytes: u64,
+    // (width, height, format) of the frames in this segment
+    frame_layout: Option<(u32, u32, DXGI_FORMAT)>,
+    started_at: Instant,
+}
+
+impl Segment {
+    fn create(output_dir: &Path, name: &str, format: SegmentFormat) -> Result<Self> {
+        let (path, writer) = match format {
+            SegmentFormat::Raw => {
+                let path = output_dir.join(format!("{}.raw", name));
+                let writer = BufWriter::new(File::create(&path)?);
+                (path, Some(writer))
+            }
+            #[cfg(feature = "png")]
+            SegmentFormat::Png => {
+                let path = output_dir.join(name);
+                std::fs::create_dir_all(&path)?;
+                (path, None)
+            }
+        };
+        let mut index = BufWriter::new(File::create(output_dir.join(format!("{}.csv", name)))?);
+        writeln!(
+            index,
+            "frame,timestamp_ns,width,height,dxgi_format,location"
+        )?;
+        Ok(Self {
+            path,
+            writer,
+            index,
+            frames: 0,
+            bytes: 0,
+            frame_layout: None,
+            started_at: Instant::now(),
+        })
+    }
+
+    /// Whether this segment has reached either of the limits. Empty segments are never full, so
+    /// that every segment contains at least one frame.
+    fn is_full(&self, max_frames: Option<usize>, max_duration: Option<Duration>) -> bool {
+        if self.frames == 0 {
+            return false;
+        }
+        let frames_exceeded = max_frames.is_some_and(|max| self.frames >= max);
+        let duration_exceeded = max_duration.is_some_and(|max| self.started_at.elapsed() >= max);
+        frames_exceeded || duration_exceeded
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        let location = match self.writer.as_mut() {
+            Some(writer) => {
+                frame.write_packed(writer)?;
+                let offset = self.bytes;
+                self.bytes += frame.packed_len() as u64;
+                offset.to_string()
+            }
+            #[cfg(feature = "png")]
+            None => {
+                let file_name = format!("{:06}.png", self.frames);
+                frame.to_owned()?.save_png(&self.path.join(&file_name))?;
+                file_name
+            }
+            #[cfg(not(feature = "png"))]
+            None => unreachable!("raw segments always have a writer"),
+        };
+        let desc = frame.desc();
+        writeln!(
+            self.index,
+            "{},{},{},{},{},{}",
+            self.frames,
+            frame.timestamp.as_nanos(),
+            desc.Width,
+            desc.Height,
+            desc.Format.0,
+            location
+        )?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flush the files of this segment and get its path.
+    fn finish(mut self) -> Result<PathBuf> {
+        self.index.flush()?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        Ok(self.path)
+    }
+}
+
+impl SegmentedRecorder {
+    pub fn new(capture: Capture, output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            capture,
+            output_dir: output_dir.into(),
+            prefix: "segment".to_string(),
+            format: SegmentFormat::Raw,
+            max_frames_per_segment: None,
+            max_segment_duration: None,
+            on_rollover: None,
+            segment_index: 0,
+            segment: None,
+        }
+    }
+
+    pub fn set_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn set_format(mut self, val: SegmentFormat) -> Self {
+        self.format = val;
+        self
+    }
+
+    pub fn set_max_frames_per_segment(mut self, val: usize) -> Self {
+        self.max_frames_per_segment = Some(val);
+        self
+    }
+
+    pub fn set_max_segment_duration(mut self, val: Duration) -> Self {
+        self.max_segment_duration = Some(val);
+        self
+    }
+
+    /// Set a callback to be invoked every time a segment is finished. It receives the index of the
+    /// segment and the path to its `.raw` file or PNG directory.
+    pub fn set_on_rollover(mut self, callback: RolloverCallback) -> Self {
+        self.on_rollover = Some(callback);
+        self
+    }
+
+    /// Get the underlying capture.
+    pub fn capture(&mut self) -> &mut Capture {
+        &mut self.capture
+    }
+
+    /// Start capturing and open the first segment.
+    pub fn start(&mut self) -> Result<()> {
+        if !self.capture.has_cpu_access() {
+            return Err(Error::new(
+                E_INVALIDARG,
+                "SegmentedRecorder requires a capture with CPU access",
+            )
+            .into());
+        }
+        std::fs::create_dir_all(&self.output_dir)?;
+        self.capture.start()?;
+        self.open_segment()
+    }
+
+    /// Grab the next frame and append it to the current segment, rolling over if needed.
+    ///
+    /// Returns `Ok(false)` when the capture has ended and no more frames will be recorded.
+    pub fn record_next(&mut self) -> Result<bool> {
+        if self.needs_rollover() {
+            self.finish_segment()?;
+            self.segment_index += 1;
+            self.open_segment()?;
+        }
+        let Some(frame) = self.capture.grab()? else {
+            return Ok(false);
+        };
+        let desc = frame.desc();
+        let layout = Some((desc.Width, desc.Height, desc.Format));
+        if self
+            .segment
+            .as_ref()
+            .is_some_and(|segment| segment.frames > 0 && segment.frame_layout != layout)
+        {
+            // frames of different sizes can't be told apart in a raw segment
+            self.finish_segment()?;
+            self.segment_index += 1;
+            self.open_segment()?;
+        }
+        if let Some(segment) = self.segment.as_mut() {
+            segment.frame_layout = layout;
+            segment.write_frame(&frame)?;
+        }
+        Ok(true)
+    }
+
+    /// Stop capturing and finish the current segment.
+    pub fn stop(&mut self) -> Result<()> {
+        self.finish_segment()?;
+        self.capture.stop()
+    }
+
+    fn needs_rollover(&self) -> bool {
+        self.segment.as_ref().is_some_and(|segment| {
+            segment.is_full(self.max_frames_per_segment, self.max_segment_duration)
+        })
+    }
+
+    fn open_segment(&mut self) -> Result<()> {
+        let name = format!("{}_{:05}", self.prefix, self.segment_index);
+        self.segment = Some(Segment::create(&self.output_dir, &name, self.format)?);
+        Ok(())
+    }
+
+    fn finish_segment(&mut self) -> Result<()> {
+        let Some(segment) = self.segment.take() else {
+            return Ok(());
+        };
+        let path = segment.finish()?;
+        if let Some(callback) = self.on_rollover.as_mut() {
+            callback(self.segment_index, &path);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SegmentedRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_segment() {
+            log::warn!("failed to finish the last segment: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_rollover(format: SegmentFormat) {
+        let output_dir =
+            std::env::temp_dir().join(format!("zbl-test-{}-{:?}", std::process::id(), format));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let Ok(mut segment) = Segment::create(&output_dir, "segment", format) else {
+            panic!("failed to create a segment in {}", output_dir.display());
+        };
+        // empty segments are kept open regardless of the limits
+        assert!(!segment.is_full(Some(1), Some(Duration::ZERO)));
+        segment.frames = 1;
+        assert!(!segment.is_full(None, None));
+        assert!(!segment.is_full(Some(2), Some(Duration::from_secs(3600))));
+        assert!(segment.is_full(Some(1), None));
+        assert!(segment.is_full(None, Some(Duration::ZERO)));
+        drop(segment);
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn raw_segment_rollover() {
+        check_rollover(SegmentFormat::Raw);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_segment_rollover() {
+        check_rollover(SegmentFormat::Png);
+    }
+}
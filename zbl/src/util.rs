@@ -1,3 +1,8 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
 pub fn convert_u16_string(input: &[u16]) -> String {
     let mut s = String::from_utf16_lossy(input);
     if let Some(index) = s.find('\0') {
@@ -5,3 +10,76 @@ pub fn convert_u16_string(input: &[u16]) -> String {
     }
     s
 }
+
+/// Convert the first `len` chars of a buffer filled by `GetWindowTextW`/`GetClassNameW`. These
+/// return 0 both for empty strings and on failure, in which case the result is an empty string.
+pub fn convert_u16_string_with_len(buf: &[u16], len: i32) -> String {
+    if len <= 0 {
+        return String::new();
+    }
+    String::from_utf16_lossy(&buf[..(len as usize).min(buf.len())])
+}
+
+/// Frame rate counter over a sliding window of the last second.
+#[derive(Clone, Debug, Default)]
+pub struct FpsCounter {
+    ticks: VecDeque<Instant>,
+}
+
+impl FpsCounter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    /// Register a frame.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        while self
+            .ticks
+            .front()
+            .is_some_and(|&tick| now.duration_since(tick) >= Self::WINDOW)
+        {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(now);
+    }
+
+    /// Get the number of frames registered during the last second.
+    pub fn fps(&self) -> f32 {
+        let now = Instant::now();
+        self.ticks
+            .iter()
+            .rev()
+            .take_while(|&&tick| now.duration_since(tick) < Self::WINDOW)
+            .count() as f32
+    }
+
+    /// Forget all registered frames.
+    pub fn reset(&mut self) {
+        self.ticks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn string_with_zero_len_is_empty() {
+        assert_eq!(convert_u16_string_with_len(&utf16("title"), 0), "");
+        assert_eq!(convert_u16_string_with_len(&[], 0), "");
+    }
+
+    #[test]
+    fn string_with_negative_len_is_empty() {
+        assert_eq!(convert_u16_string_with_len(&utf16("title"), -1), "");
+    }
+
+    #[test]
+    fn string_is_limited_by_len_and_buffer() {
+        assert_eq!(convert_u16_string_with_len(&utf16("title"), 3), "tit");
+        assert_eq!(convert_u16_string_with_len(&utf16("title"), 100), "title");
+    }
+}
@@ -0,0 +1,356 @@
+use std::{path::Path, time::Duration};
+
+use windows::{
+    core::{Error, Interface, HSTRING},
+    Graphics::DirectX::DirectXPixelFormat,
+    Win32::{
+        Foundation::{E_ILLEGAL_METHOD_CALL, E_INVALIDARG},
+        Graphics::{Direct3D10::ID3D10Multithread, Direct3D11::ID3D11Texture2D},
+        Media::MediaFoundation::{
+            IMF2DBuffer, IMFAttributes, IMFDXGIDeviceManager, IMFMediaBuffer, IMFMediaType,
+            IMFSinkWriter, MFCreateAttributes, MFCreateDXGIDeviceManager,
+            MFCreateDXGISurfaceBuffer, MFCreateMediaType, MFCreateMemoryBuffer, MFCreateSample,
+            MFCreateSinkWriterFromURL, MFMediaType_Video, MFShutdown, MFStartup,
+            MFVideoFormat_H264, MFVideoFormat_NV12, MFVideoFormat_RGB32,
+            MFVideoInterlace_Progressive, MFSTARTUP_FULL, MF_MT_AVG_BITRATE, MF_MT_DEFAULT_STRIDE,
+            MF_MT_FRAME_RATE, MF_MT_FRAME_SIZE, MF_MT_INTERLACE_MODE, MF_MT_MAJOR_TYPE,
+            MF_MT_PIXEL_ASPECT_RATIO, MF_MT_SUBTYPE, MF_READWRITE_ENABLE_HARDWARE_TRANSFORMS,
+            MF_SINK_WRITER_D3D_MANAGER, MF_VERSION,
+        },
+    },
+};
+
+use crate::Result;
+
+use crate::{
+    capture::{Capture, MaybeFrame, OutputFormat},
+    frame::Frame,
+};
+
+/// How often the capture is polled if the refresh rate of its target is unknown.
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Records frames from a `Capture` into an MP4 file encoded with H.264, using Media Foundation.
+///
+/// If the capture has no CPU access, frame textures are fed to the encoder directly through the
+/// DXGI device manager. Otherwise, frames are copied into memory buffers.
+///
+/// Captures with `OutputFormat::Nv12` are fed to the encoder as NV12, which saves the encoder a
+/// color conversion. Otherwise, frames are fed as RGB32.
+///
+/// H.264 requires even frame dimensions, so odd-sized content is cropped by one pixel. For
+/// captures without CPU access, this means frames of odd size can't be recorded - enable CPU
+/// access for such targets.
+pub struct Recorder {
+    capture: Capture,
+    path: HSTRING,
+    bitrate: u32,
+    fps: u32,
+    writer: Option<IMFSinkWriter>,
+    // keeps the device shared with the encoder alive while recording
+    _device_manager: Option<IMFDXGIDeviceManager>,
+    stream_index: u32,
+    frame_size: (u32, u32),
+    // capture timestamp of the first recorded frame, which is at time 0 of the video
+    first_timestamp: Option<Duration>,
+    last_sample_time: Option<i64>,
+    poll_interval: Duration,
+}
+
+impl Recorder {
+    pub fn new(capture: Capture, path: &Path) -> Self {
+        Self {
+            capture,
+            path: HSTRING::from(path),
+            bitrate: 8_000_000,
+            fps: 60,
+            writer: None,
+            _device_manager: None,
+            stream_index: 0,
+            frame_size: (0, 0),
+            first_timestamp: None,
+            last_sample_time: None,
+            poll_interval: POLL_INTERVAL,
+        }
+    }
+
+    /// Set the target bitrate of the video, in bits per second. Must be called before `start()`.
+    pub fn set_bitrate(&mut self, bitrate: u32) -> Result<()> {
+        self.ensure_not_started()?;
+        self.bitrate = bitrate;
+        Ok(())
+    }
+
+    /// Set the nominal frame rate of the video. Must be called before `start()`.
+    ///
+    /// Frames are timestamped with the time they were captured at (see `Frame::timestamp`),
+    /// relative to the first recorded frame, so this doesn't affect the playback speed.
+    pub fn set_fps(&mut self, fps: u32) -> Result<()> {
+        self.ensure_not_started()?;
+        self.fps = fps;
+        Ok(())
+    }
+
+    /// Get the underlying capture.
+    pub fn capture(&mut self) -> &mut Capture {
+        &mut self.capture
+    }
+
+    /// Start capturing and initialize the encoder.
+    pub fn start(&mut self) -> Result<()> {
+        self.ensure_not_started()?;
+        if self.capture.pixel_format() != DirectXPixelFormat::B8G8R8A8UIntNormalized {
+            return Err(Error::new(
+                E_INVALIDARG,
+                "only captures with B8G8R8A8UIntNormalized pixel format can be recorded",
+            )
+            .into());
+        }
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? };
+        let writer = match self.create_writer() {
+            Ok(writer) => writer,
+            Err(e) => {
+                self._device_manager = None;
+                let _ = unsafe { MFShutdown() };
+                return Err(e);
+            }
+        };
+        self.writer = Some(writer);
+        if let Err(e) = self.capture.start() {
+            let _ = self.finalize();
+            return Err(e);
+        }
+        self.first_timestamp = None;
+        self.last_sample_time = None;
+        self.poll_interval = self.capture.frame_interval_hint().unwrap_or(POLL_INTERVAL);
+        Ok(())
+    }
+
+    /// Create the sink writer and configure its stream. Media Foundation must be started.
+    fn create_writer(&mut self) -> Result<IMFSinkWriter> {
+        let (width, height) = self.capture.content_size();
+        self.frame_size = (width & !1, height & !1);
+
+        let mut attributes: Option<IMFAttributes> = None;
+        unsafe { MFCreateAttributes(&mut attributes, 2)? };
+        let attributes = attributes.expect("MFCreateAttributes returned nullptr");
+        unsafe { attributes.SetUINT32(&MF_READWRITE_ENABLE_HARDWARE_TRANSFORMS, 1)? };
+
+        if !self.capture.has_cpu_access() {
+            let device = self.capture.device();
+            // the device is now going to be used by the encoder as well
+            let multithread: ID3D10Multithread = device.cast()?;
+            let _ = unsafe { multithread.SetMultithreadProtected(true) };
+
+            let mut reset_token = 0u32;
+            let mut device_manager: Option<IMFDXGIDeviceManager> = None;
+            unsafe { MFCreateDXGIDeviceManager(&mut reset_token, &mut device_manager)? };
+            let device_manager =
+                device_manager.expect("MFCreateDXGIDeviceManager returned nullptr");
+            unsafe {
+                device_manager.ResetDevice(device, reset_token)?;
+                attributes.SetUnknown(&MF_SINK_WRITER_D3D_MANAGER, &device_manager)?;
+            }
+            self._device_manager = Some(device_manager);
+        }
+
+        let writer = unsafe { MFCreateSinkWriterFromURL(&self.path, None, &attributes)? };
+
+        let output_type = self.create_media_type(&MFVideoFormat_H264)?;
+        unsafe { output_type.SetUINT32(&MF_MT_AVG_BITRATE, self.bitrate)? };
+        self.stream_index = unsafe { writer.AddStream(&output_type)? };
+
+        let (input_subtype, stride) = match self.capture.output_format() {
+            OutputFormat::Native => (MFVideoFormat_RGB32, self.frame_size.0 * 4),
+            // the stride of NV12 is the stride of its Y plane
+            OutputFormat::Nv12 => (MFVideoFormat_NV12, self.frame_size.0),
+        };
+        let input_type = self.create_media_type(&input_subtype)?;
+        // positive stride means top-down rows, which is how frames are laid out
+        unsafe {
+            input_type.SetUINT32(&MF_MT_DEFAULT_STRIDE, stride)?;
+            writer.SetInputMediaType(self.stream_index, &input_type, None)?;
+            writer.BeginWriting()?;
+        }
+        Ok(writer)
+    }
+
+    /// Grab the next frame and feed it to the encoder. If there is no new frame yet, this sleeps
+    /// for one frame interval (see `Capture::frame_interval_hint`) and returns, so that a loop
+    /// calling it doesn't spin and can still check its own stop condition.
+    ///
+    /// Returns `Ok(false)` when the capture has ended and no more frames will be recorded.
+    pub fn record_next(&mut self) -> Result<bool> {
+        match self.capture.try_grab()? {
+            MaybeFrame::Some(frame) => {
+                self.write_frame(&frame)?;
+                Ok(true)
+            }
+            MaybeFrame::Pending | MaybeFrame::Minimized => {
+                std::thread::sleep(self.poll_interval);
+                Ok(true)
+            }
+            MaybeFrame::None => Ok(false),
+        }
+    }
+
+    /// Stop capturing and finalize the video file. Media Foundation is shut down even if
+    /// finalizing fails.
+    pub fn stop(&mut self) -> Result<()> {
+        let finalized = self.finalize();
+        if !self.capture.is_stopped() {
+            self.capture.stop()?;
+        }
+        finalized
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        let finalized = unsafe { writer.Finalize() };
+        // Media Foundation objects have to be released before shutting it down
+        drop(writer);
+        self._device_manager = None;
+        let shutdown = unsafe { MFShutdown() };
+        finalized?;
+        Ok(shutdown?)
+    }
+
+    fn ensure_not_started(&self) -> Result<()> {
+        if self.writer.is_some() {
+            return Err(Error::new(
+                E_ILLEGAL_METHOD_CALL,
+                "the recording has already been started",
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn create_media_type(&self, subtype: &windows::core::GUID) -> Result<IMFMediaType> {
+        let (width, height) = self.frame_size;
+        unsafe {
+            let media_type = MFCreateMediaType()?;
+            media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
+            media_type.SetGUID(&MF_MT_SUBTYPE, subtype)?;
+            media_type.SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)?;
+            media_type.SetUINT64(&MF_MT_FRAME_SIZE, ((width as u64) << 32) | height as u64)?;
+            media_type.SetUINT64(&MF_MT_FRAME_RATE, ((self.fps as u64) << 32) | 1)?;
+            media_type.SetUINT64(&MF_MT_PIXEL_ASPECT_RATIO, (1 << 32) | 1)?;
+            Ok(media_type)
+        }
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        let Some(writer) = self.writer.as_ref() else {
+            return Ok(());
+        };
+        // the frame is fed to the encoder before the next one is grabbed
+        let buffer = match unsafe { frame.as_slice() } {
+            Some(data) => self.create_memory_buffer(frame, data)?,
+            None => match self.create_surface_buffer(&frame.texture)? {
+                Some(buffer) => buffer,
+                None => return Ok(()),
+            },
+        };
+
+        let first_timestamp = *self.first_timestamp.get_or_insert(frame.timestamp);
+        // Media Foundation uses 100ns units
+        let mut sample_time =
+            (frame.timestamp.saturating_sub(first_timestamp).as_nanos() / 100) as i64;
+        if let Some(last_sample_time) = self.last_sample_time {
+            sample_time = sample_time.max(last_sample_time + 1);
+        }
+        self.last_sample_time = Some(sample_time);
+
+        unsafe {
+            let sample = MFCreateSample()?;
+            sample.AddBuffer(&buffer)?;
+            sample.SetSampleTime(sample_time)?;
+            sample.SetSampleDuration(10_000_000 / self.fps.max(1) as i64)?;
+            writer.WriteSample(self.stream_index, &sample)?;
+        }
+        Ok(())
+    }
+
+    fn create_memory_buffer(&self, frame: &Frame, data: &[u8]) -> Result<IMFMediaBuffer> {
+        let (width, height) = self.frame_size;
+        let row_pitch = frame.row_pitch() as usize;
+        // the target might have been resized since the recording has started
+        let desc = frame.desc();
+        let is_smaller = desc.Width < width || desc.Height < height;
+        let len = match self.capture.output_format() {
+            OutputFormat::Native => width as usize * 4 * height as usize,
+            OutputFormat::Nv12 => width as usize * (height as usize + height as usize / 2),
+        };
+        unsafe {
+            let buffer = MFCreateMemoryBuffer(len as u32)?;
+            let mut ptr = std::ptr::null_mut();
+            buffer.Lock(&mut ptr, None, None)?;
+            let dst = std::slice::from_raw_parts_mut(ptr, len);
+            match frame.nv12_planes() {
+                Some((y, uv)) => {
+                    let (dst_y, dst_uv) = dst.split_at_mut(width as usize * height as usize);
+                    if is_smaller {
+                        // black in studio range
+                        dst_y.fill(16);
+                        dst_uv.fill(128);
+                    }
+                    copy_rows(dst_y, y, width as usize, row_pitch);
+                    copy_rows(dst_uv, uv, width as usize, row_pitch);
+                }
+                None => {
+                    if is_smaller {
+                        dst.fill(0);
+                    }
+                    copy_rows(dst, data, width as usize * 4, row_pitch);
+                }
+            }
+            buffer.Unlock()?;
+            buffer.SetCurrentLength(len as u32)?;
+            Ok(buffer)
+        }
+    }
+
+    fn create_surface_buffer(&self, texture: &ID3D11Texture2D) -> Result<Option<IMFMediaBuffer>> {
+        let desc = {
+            let mut desc = Default::default();
+            unsafe { texture.GetDesc(&mut desc) };
+            desc
+        };
+        if (desc.Width, desc.Height) != self.frame_size {
+            log::warn!(
+                "skipping frame of size {}x{}, recording size is {}x{}",
+                desc.Width,
+                desc.Height,
+                self.frame_size.0,
+                self.frame_size.1
+            );
+            return Ok(None);
+        }
+        unsafe {
+            let buffer = MFCreateDXGISurfaceBuffer(&ID3D11Texture2D::IID, texture, 0, false)?;
+            let buffer_2d: IMF2DBuffer = buffer.cast()?;
+            buffer.SetCurrentLength(buffer_2d.GetContiguousLength()?)?;
+            Ok(Some(buffer))
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finalize() {
+            log::warn!("failed to finalize the recording: {}", e);
+        }
+    }
+}
+
+/// Copy rows of `src` (`row_pitch` bytes apart) into tightly packed rows of `row_size` bytes in
+/// `dst`, truncating whatever doesn't fit.
+fn copy_rows(dst: &mut [u8], src: &[u8], row_size: usize, row_pitch: usize) {
+    for (dst_row, src_row) in dst.chunks_mut(row_size).zip(src.chunks(row_pitch)) {
+        let n = dst_row.len().min(src_row.len());
+        dst_row[..n].copy_from_slice(&src_row[..n]);
+    }
+}
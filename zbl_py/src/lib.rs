@@ -1,15 +1,32 @@
 use ::zbl::windows::{core::Interface, Win32::Foundation::HWND};
-use pyo3::{exceptions::PyRuntimeError, prelude::*};
-use std::ffi::c_void;
+use pyo3::{
+    exceptions::{PyBufferError, PyRuntimeError, PyValueError},
+    ffi,
+    prelude::*,
+    types::PyBytes,
+};
+use std::{
+    collections::HashMap,
+    ffi::{c_int, c_void},
+    ptr::null_mut,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("window with given name not found: {0}")]
-    WindowNotFoundError(String),
-    #[error("windows api error: {0}")]
-    WindowsError(#[from] ::zbl::windows::core::Error),
+    #[error("{0}")]
+    ZblError(#[from] ::zbl::Error),
     #[error("frame channel error")]
     FrameChannelError(#[from] std::sync::mpsc::RecvError),
+    #[error("window belonging to process {0} not found")]
+    ProcessWindowNotFoundError(u32),
+    #[error("window with given class name not found: {0}")]
+    WindowClassNotFoundError(String),
+    #[error("unknown pixel format '{0}', expected 'bgra8' or 'rgba16f'")]
+    UnknownPixelFormat(String),
+    #[error("unknown output format '{0}', expected 'native' or 'nv12'")]
+    UnknownOutputFormat(String),
+    #[error("display of window {0} not found")]
+    WindowDisplayNotFoundError(isize),
     #[error("neither name nor handle is set")]
     NeitherNameNorHandleIsSet,
 }
@@ -22,16 +39,275 @@ impl From<Error> for PyErr {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+fn parse_pixel_format(name: &str) -> Result<::zbl::windows::Graphics::DirectX::DirectXPixelFormat> {
+    use ::zbl::windows::Graphics::DirectX::DirectXPixelFormat;
+    match name {
+        "bgra8" => Ok(DirectXPixelFormat::B8G8R8A8UIntNormalized),
+        "rgba16f" => Ok(DirectXPixelFormat::R16G16B16A16Float),
+        _ => Err(Error::UnknownPixelFormat(name.to_string())),
+    }
+}
+
+fn parse_output_format(name: &str) -> Result<::zbl::OutputFormat> {
+    match name {
+        "native" => Ok(::zbl::OutputFormat::Native),
+        "nv12" => Ok(::zbl::OutputFormat::Nv12),
+        _ => Err(Error::UnknownOutputFormat(name.to_string())),
+    }
+}
+
+/// Reject frame rate limits which `CaptureBuilder::build` would fail on, as a `ValueError`.
+fn check_max_fps(max_fps: Option<f32>) -> PyResult<()> {
+    match max_fps {
+        Some(fps) if !(fps > 0.0 && fps.is_finite()) => Err(PyValueError::new_err(format!(
+            "max_fps must be positive and finite, got {}",
+            fps
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Convert a duration in seconds passed from Python, raising `ValueError` for negative, NaN or
+/// overflowing values.
+fn duration_from_secs(name: &str, secs: f64) -> PyResult<std::time::Duration> {
+    std::time::Duration::try_from_secs_f64(secs)
+        .map_err(|e| PyValueError::new_err(format!("invalid {} {}: {}", name, secs, e)))
+}
+
+fn pixel_format_name(format: ::zbl::windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT) -> String {
+    use ::zbl::windows::Win32::Graphics::Dxgi::Common::{
+        DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_NV12, DXGI_FORMAT_R16G16B16A16_FLOAT,
+    };
+    match format {
+        DXGI_FORMAT_B8G8R8A8_UNORM => "bgra8".to_string(),
+        DXGI_FORMAT_R16G16B16A16_FLOAT => "rgba16f".to_string(),
+        DXGI_FORMAT_NV12 => "nv12".to_string(),
+        format => format!("dxgi_format_{}", format.0),
+    }
+}
+
+enum FrameData {
+    /// Frame backed by the capture's texture, which is reused between grabs.
+    Texture(::zbl::Frame),
+    /// Frame with its own copy of pixels.
+    Owned(::zbl::OwnedFrame),
+}
+
 #[pyclass(unsendable)]
 pub struct Frame {
     width: u32,
     height: u32,
     row_pitch: u32,
+    bytes_per_pixel: u32,
     ptr: *mut c_void,
+    cpu_access: bool,
+    // keeps the backing data alive for as long as this frame (or a buffer exported from it) exists
+    inner: FrameData,
+    // these are referenced by exported buffers, so they have to live as long as the frame
+    shape: [ffi::Py_ssize_t; 3],
+    strides: [ffi::Py_ssize_t; 3],
+}
+
+/// Get the shape and strides of the buffer exported for a frame.
+fn buffer_layout(
+    format: ::zbl::windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT,
+    width: u32,
+    height: u32,
+    row_pitch: u32,
+) -> ([ffi::Py_ssize_t; 3], [ffi::Py_ssize_t; 3]) {
+    use ::zbl::windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_NV12;
+    if format == DXGI_FORMAT_NV12 {
+        // the Y plane followed by the interleaved UV plane, one byte per sample
+        let rows = ::zbl::frame::row_count(format, height);
+        return ([rows as _, width as _, 1], [row_pitch as _, 1, 1]);
+    }
+    let bytes_per_pixel = ::zbl::frame::bytes_per_pixel(format);
+    // 4 channels per pixel
+    let channel_size = bytes_per_pixel / 4;
+    (
+        [height as _, width as _, 4],
+        [row_pitch as _, bytes_per_pixel as _, channel_size as _],
+    )
+}
+
+impl Frame {
+    fn new(frame: ::zbl::Frame, cpu_access: bool) -> Self {
+        let desc = frame.desc();
+        let row_pitch = frame.row_pitch();
+        let bytes_per_pixel = frame.bytes_per_pixel();
+        let (shape, strides) = buffer_layout(desc.Format, desc.Width, desc.Height, row_pitch);
+        Self {
+            width: desc.Width,
+            height: desc.Height,
+            row_pitch,
+            bytes_per_pixel,
+            ptr: if cpu_access {
+                frame.mapped_ptr.pData
+            } else {
+                frame.texture.as_raw()
+            },
+            cpu_access,
+            shape,
+            strides,
+            inner: FrameData::Texture(frame),
+        }
+    }
+
+    fn from_owned(frame: ::zbl::OwnedFrame) -> Self {
+        let bytes_per_pixel = frame.bytes_per_pixel();
+        let row_pitch = frame.width * bytes_per_pixel;
+        let (shape, strides) = buffer_layout(frame.format, frame.width, frame.height, row_pitch);
+        Self {
+            width: frame.width,
+            height: frame.height,
+            row_pitch,
+            bytes_per_pixel,
+            ptr: frame.data.as_ptr() as *mut c_void,
+            cpu_access: true,
+            shape,
+            strides,
+            inner: FrameData::Owned(frame),
+        }
+    }
 }
 
 #[pymethods]
 impl Frame {
+    /// Exposes the frame's pixels as a read-only `(height, width, 4)` buffer, which allows
+    /// `numpy.asarray(frame)` to work without copying. The buffer contains bytes for `bgra8`
+    /// frames, and 16-bit floats for `rgba16f` frames. `nv12` frames are exposed as a
+    /// `(height * 3 / 2, width, 1)` byte buffer: the Y plane followed by the interleaved UV plane.
+    ///
+    /// Note that the data is only valid until the next `grab`, since the backing texture is reused.
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("view is null"));
+        }
+        if (flags & ffi::PyBUF_WRITABLE) == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("frame buffer is read-only"));
+        }
+
+        let frame = slf.borrow();
+        if !frame.cpu_access {
+            return Err(PyBufferError::new_err(
+                "frame buffer is only available for captures with cpu_access enabled",
+            ));
+        }
+        // 8-byte pixels are 16-bit floats, which can't be described without format
+        let is_float = frame.bytes_per_pixel == 8;
+        if is_float && (flags & ffi::PyBUF_FORMAT) != ffi::PyBUF_FORMAT {
+            return Err(PyBufferError::new_err(
+                "frame buffer contains 16-bit floats, format is required",
+            ));
+        }
+        let is_contiguous = frame.row_pitch == frame.width * frame.bytes_per_pixel;
+        if (flags & ffi::PyBUF_STRIDES) != ffi::PyBUF_STRIDES && !is_contiguous {
+            return Err(PyBufferError::new_err(
+                "frame buffer is not contiguous, strides are required",
+            ));
+        }
+
+        (*view).buf = frame.ptr;
+        (*view).itemsize = frame.strides[2];
+        (*view).len = frame.shape.iter().product::<ffi::Py_ssize_t>() * (*view).itemsize;
+        (*view).readonly = 1;
+        (*view).format = if is_float {
+            c"e".as_ptr() as *mut _
+        } else if (flags & ffi::PyBUF_FORMAT) == ffi::PyBUF_FORMAT {
+            c"B".as_ptr() as *mut _
+        } else {
+            null_mut()
+        };
+        (*view).ndim = 3;
+        (*view).shape = frame.shape.as_ptr() as *mut _;
+        (*view).strides = if (flags & ffi::PyBUF_STRIDES) == ffi::PyBUF_STRIDES {
+            frame.strides.as_ptr() as *mut _
+        } else {
+            null_mut()
+        };
+        (*view).suboffsets = null_mut();
+        (*view).internal = null_mut();
+
+        drop(frame);
+        // the buffer holds a reference to the frame, so the frame can't be freed while in use
+        (*view).obj = slf.into_any().into_ptr();
+
+        Ok(())
+    }
+
+    unsafe fn __releasebuffer__(&self, _view: *mut ffi::Py_buffer) {}
+
+    /// Copy the frame's pixels, in its format, into tightly packed `bytes` (i.e. without row
+    /// padding). NV12 frames have the UV plane following the Y plane.
+    pub fn to_bytes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let no_cpu_access = || {
+            PyRuntimeError::new_err(
+                "frame data is only available for captures with cpu_access enabled",
+            )
+        };
+        if !self.cpu_access {
+            return Err(no_cpu_access());
+        }
+        match &self.inner {
+            FrameData::Texture(frame) => {
+                Ok(PyBytes::new(py, &frame.to_vec().ok_or_else(no_cpu_access)?))
+            }
+            FrameData::Owned(frame) => Ok(PyBytes::new(py, &frame.data)),
+        }
+    }
+
+    /// Copy the frame into a new `Frame` which stays valid after subsequent grabs.
+    pub fn copy(&self) -> PyResult<Frame> {
+        let owned = match &self.inner {
+            FrameData::Texture(frame) => frame.to_owned().map_err(Error::from)?,
+            FrameData::Owned(frame) => frame.clone(),
+        };
+        Ok(Frame::from_owned(owned))
+    }
+
+    /// Save the frame as a PNG image.
+    pub fn save_png(&self, path: std::path::PathBuf) -> PyResult<()> {
+        let result = match &self.inner {
+            FrameData::Texture(frame) => ::zbl::save_frame_png(frame, &path),
+            FrameData::Owned(frame) => frame.save_png(&path),
+        };
+        Ok(result.map_err(Error::from)?)
+    }
+
+    /// Capture time of the frame relative to system boot, in seconds.
+    #[getter]
+    pub fn timestamp(&self) -> f64 {
+        match &self.inner {
+            FrameData::Texture(frame) => frame.timestamp.as_secs_f64(),
+            FrameData::Owned(frame) => frame.timestamp.as_secs_f64(),
+        }
+    }
+
+    /// Number of frames that have arrived before this one. Gaps in this number mean that frames
+    /// have been dropped.
+    #[getter]
+    pub fn sequence(&self) -> u64 {
+        match &self.inner {
+            FrameData::Texture(frame) => frame.sequence,
+            FrameData::Owned(frame) => frame.sequence,
+        }
+    }
+
+    /// `(x, y, visible)` of the mouse cursor in frame coordinates, or `None` if the cursor is
+    /// outside the frame or the capture was created without `capture_cursor_metadata=True`.
+    #[getter]
+    pub fn cursor(&self) -> Option<(i32, i32, bool)> {
+        let cursor = match &self.inner {
+            FrameData::Texture(frame) => frame.cursor,
+            FrameData::Owned(frame) => frame.cursor,
+        }?;
+        Some((cursor.x, cursor.y, cursor.visible))
+    }
+
     #[getter]
     pub fn width(&self) -> usize {
         self.width as usize
@@ -51,80 +327,176 @@ impl Frame {
     pub fn ptr(&self) -> usize {
         self.ptr as usize
     }
+
+    /// Pixel format of the frame, e.g. `"bgra8"`, `"rgba16f"` or `"nv12"`.
+    #[getter]
+    pub fn format(&self) -> String {
+        pixel_format_name(match &self.inner {
+            FrameData::Texture(frame) => frame.format(),
+            FrameData::Owned(frame) => frame.format,
+        })
+    }
+
+    #[getter]
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel as usize
+    }
+
+    /// Shape of the frame's buffer, `(height, width, channels)` - see `__getbuffer__`. Together
+    /// with `strides` this allows wrapping the data without knowing the pixel format.
+    #[getter]
+    pub fn shape(&self) -> (usize, usize, usize) {
+        let [rows, columns, channels] = self.shape;
+        (rows as usize, columns as usize, channels as usize)
+    }
+
+    /// Strides of the frame's buffer in bytes, `(row_pitch, bytes_per_pixel, channel_size)`.
+    #[getter]
+    pub fn strides(&self) -> (usize, usize, usize) {
+        let [row, column, channel] = self.strides;
+        (row as usize, column as usize, channel as usize)
+    }
+}
+
+/// Settings applied to `CaptureBuilder` when creating a `Capture`.
+pub struct CaptureOptions {
+    pub is_cursor_capture_enabled: bool,
+    pub is_border_required: bool,
+    /// See `CaptureBuilder::set_allow_border_change_failure`.
+    pub allow_border_change_failure: bool,
+    pub cpu_access: bool,
+    pub max_fps: Option<f32>,
+    pub latest_only: bool,
+    pub pixel_format: ::zbl::windows::Graphics::DirectX::DirectXPixelFormat,
+    pub output_format: ::zbl::OutputFormat,
+    /// Region of the client area to capture, see `CaptureBuilder::set_client_crop`.
+    pub client_crop: Option<::zbl::windows::Win32::Foundation::RECT>,
+    /// How long to wait for a window to appear when capturing by name.
+    pub wait_timeout: Option<std::time::Duration>,
+    pub capture_cursor_metadata: bool,
+    pub min_update_interval: Option<std::time::Duration>,
+    /// Start capturing on the first grab if `start()` hasn't been called.
+    pub auto_start: bool,
 }
 
 #[pyclass(unsendable)]
 pub struct Capture {
     inner: ::zbl::Capture,
+    auto_start: bool,
 }
 
 impl Capture {
     pub fn from_capturable(
         capturable: Box<dyn ::zbl::Capturable>,
-        is_cursor_capture_enabled: bool,
-        is_border_required: bool,
-        cpu_access: bool,
+        options: &CaptureOptions,
     ) -> Result<Self> {
-        ::zbl::init();
+        ::zbl::init()?;
         let capture = ::zbl::CaptureBuilder::new(capturable)
-            .set_is_cursor_capture_enabled(is_cursor_capture_enabled)
-            .set_is_border_required(is_border_required)
-            .set_cpu_access(cpu_access)
+            .set_is_cursor_capture_enabled(options.is_cursor_capture_enabled)
+            .set_is_border_required(options.is_border_required)
+            .set_allow_border_change_failure(options.allow_border_change_failure)
+            .set_cpu_access(options.cpu_access)
+            .set_max_fps(options.max_fps)
+            .set_pixel_format(options.pixel_format)
+            .set_output_format(options.output_format)
+            .set_client_crop(options.client_crop)
+            .set_capture_cursor_metadata(options.capture_cursor_metadata)
+            .set_min_update_interval(options.min_update_interval)
+            .set_frame_policy(if options.latest_only {
+                ::zbl::FramePolicy::LatestOnly
+            } else {
+                ::zbl::FramePolicy::Buffered
+            })
             .build()?;
-        Ok(Self { inner: capture })
+        Ok(Self {
+            inner: capture,
+            auto_start: options.auto_start,
+        })
     }
 
-    pub fn from_window_name(
-        name: &str,
-        is_cursor_capture_enabled: bool,
-        is_border_required: bool,
-        cpu_access: bool,
-    ) -> Result<Self> {
-        let window = ::zbl::Window::find_first(name)
-            .ok_or_else(|| Error::WindowNotFoundError(name.to_string()))?;
-        Self::from_capturable(
-            Box::new(window) as Box<dyn ::zbl::Capturable>,
-            is_cursor_capture_enabled,
-            is_border_required,
-            cpu_access,
-        )
+    pub fn from_window_name(name: &str, options: &CaptureOptions) -> Result<Self> {
+        let window = match options.wait_timeout {
+            Some(timeout) => ::zbl::Window::wait_for(name, timeout)?,
+            None => ::zbl::Window::find_first(name)?,
+        };
+        Self::from_capturable(Box::new(window) as Box<dyn ::zbl::Capturable>, options)
     }
 
-    pub fn from_display_id(
-        id: usize,
-        is_cursor_capture_enabled: bool,
-        is_border_required: bool,
-        cpu_access: bool,
-    ) -> Result<Self> {
+    pub fn from_process_id(process_id: u32, options: &CaptureOptions) -> Result<Self> {
+        let window = ::zbl::Window::find_by_process_id(process_id)
+            .into_iter()
+            .next()
+            .ok_or(Error::ProcessWindowNotFoundError(process_id))?;
+        Self::from_capturable(Box::new(window) as Box<dyn ::zbl::Capturable>, options)
+    }
+
+    pub fn from_window_class(class_name: &str, options: &CaptureOptions) -> Result<Self> {
+        let window = ::zbl::Window::find_by_class_name(class_name)
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::WindowClassNotFoundError(class_name.to_string()))?;
+        Self::from_capturable(Box::new(window) as Box<dyn ::zbl::Capturable>, options)
+    }
+
+    pub fn from_display_id(id: usize, options: &CaptureOptions) -> Result<Self> {
         let display = ::zbl::Display::find_by_id(id)?;
-        Self::from_capturable(
-            Box::new(display) as Box<dyn ::zbl::Capturable>,
-            is_cursor_capture_enabled,
-            is_border_required,
-            cpu_access,
-        )
+        Self::from_capturable(Box::new(display) as Box<dyn ::zbl::Capturable>, options)
+    }
+
+    pub fn from_display_name(name: &str, options: &CaptureOptions) -> Result<Self> {
+        let display = ::zbl::Display::find_by_name(name)?;
+        Self::from_capturable(Box::new(display) as Box<dyn ::zbl::Capturable>, options)
+    }
+
+    pub fn from_primary_display(options: &CaptureOptions) -> Result<Self> {
+        let display = ::zbl::Display::primary()?;
+        Self::from_capturable(Box::new(display) as Box<dyn ::zbl::Capturable>, options)
     }
 
-    fn _start(&self) -> Result<()> {
+    fn _start(&mut self) -> Result<()> {
         Ok(self.inner.start()?)
     }
 
-    fn _grab(&mut self) -> Result<Option<Frame>> {
-        if let Some(frame) = self.inner.grab()? {
-            let desc = frame.desc();
-            Ok(Some(Frame {
-                width: desc.Width,
-                height: desc.Height,
-                row_pitch: frame.mapped_ptr.RowPitch,
-                ptr: if self.inner.has_cpu_access() {
-                    frame.mapped_ptr.pData
-                } else {
-                    frame.texture.as_raw()
-                },
-            }))
-        } else {
-            Ok(None)
+    fn _start_if_needed(&mut self) -> Result<()> {
+        if self.auto_start {
+            self.inner.start()?;
         }
+        Ok(())
+    }
+
+    fn _grab(&mut self) -> Result<Option<Frame>> {
+        self._start_if_needed()?;
+        let cpu_access = self.inner.has_cpu_access();
+        Ok(self
+            .inner
+            .grab()?
+            .map(|frame| Frame::new(frame, cpu_access)))
+    }
+
+    fn _wait_first_frame(&mut self, timeout: std::time::Duration) -> Result<bool> {
+        Ok(self.inner.wait_for_first_frame(timeout)?)
+    }
+
+    fn _grab_many(&mut self, n: usize) -> Result<Vec<Frame>> {
+        self._start_if_needed()?;
+        Ok(self
+            .inner
+            .grab_n(n)?
+            .into_iter()
+            .map(Frame::from_owned)
+            .collect())
+    }
+
+    fn _set_cursor_capture_enabled(&mut self, val: bool) -> Result<()> {
+        Ok(self.inner.set_cursor_capture_enabled(val)?)
+    }
+
+    fn _is_cursor_capture_enabled(&self) -> Result<bool> {
+        Ok(self.inner.is_cursor_capture_enabled()?)
+    }
+
+    fn _set_border_required(&mut self, val: bool) -> Result<bool> {
+        Ok(self.inner.set_border_required(val)?)
     }
 
     fn _stop(&mut self) -> Result<()> {
@@ -141,8 +513,23 @@ impl Capture {
         display_id=None,
         is_cursor_capture_enabled=false,
         is_border_required=true,
-        cpu_access=true
+        cpu_access=true,
+        display_name=None,
+        primary_display=false,
+        process_id=None,
+        window_class=None,
+        max_fps=None,
+        latest_only=false,
+        pixel_format="bgra8",
+        wait_timeout=None,
+        capture_cursor_metadata=false,
+        min_update_interval_ms=None,
+        auto_start=true,
+        output_format="native",
+        crop=None,
+        require_no_border=false
     ))]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         window_name: Option<&str>,
         window_handle: Option<i32>,
@@ -150,29 +537,69 @@ impl Capture {
         is_cursor_capture_enabled: bool,
         is_border_required: bool,
         cpu_access: bool,
+        display_name: Option<&str>,
+        primary_display: bool,
+        process_id: Option<u32>,
+        window_class: Option<&str>,
+        max_fps: Option<f32>,
+        latest_only: bool,
+        pixel_format: &str,
+        wait_timeout: Option<f64>,
+        capture_cursor_metadata: bool,
+        min_update_interval_ms: Option<u64>,
+        auto_start: bool,
+        output_format: &str,
+        crop: Option<(i32, i32, i32, i32)>,
+        require_no_border: bool,
     ) -> PyResult<Self> {
+        check_max_fps(max_fps)?;
+        // requiring no border implies disabling it
+        let options = CaptureOptions {
+            is_cursor_capture_enabled,
+            is_border_required: is_border_required && !require_no_border,
+            allow_border_change_failure: !require_no_border,
+            cpu_access,
+            max_fps,
+            latest_only,
+            pixel_format: parse_pixel_format(pixel_format)?,
+            output_format: parse_output_format(output_format)?,
+            client_crop: crop.map(|(x, y, w, h)| ::zbl::windows::Win32::Foundation::RECT {
+                left: x,
+                top: y,
+                right: x + w,
+                bottom: y + h,
+            }),
+            wait_timeout: wait_timeout
+                .map(|secs| duration_from_secs("wait_timeout", secs))
+                .transpose()?,
+            capture_cursor_metadata,
+            min_update_interval: min_update_interval_ms.map(std::time::Duration::from_millis),
+            auto_start,
+        };
         if let Some(name) = window_name {
-            Ok(Self::from_window_name(
-                name,
-                is_cursor_capture_enabled,
-                is_border_required,
-                cpu_access,
-            )?)
+            Ok(Self::from_window_name(name, &options)?)
         } else if let Some(handle) = window_handle {
             Ok(Self::from_capturable(
                 Box::new(::zbl::Window::new(HWND(handle as *mut c_void)))
                     as Box<dyn ::zbl::Capturable>,
-                is_cursor_capture_enabled,
-                is_border_required,
-                cpu_access,
+                &options,
             )?)
+        } else if let Some(class_name) = window_class {
+            Ok(Self::from_window_class(class_name, &options)?)
+        } else if let Some(process_id) = process_id {
+            Ok(Self::from_process_id(process_id, &options)?)
         } else if let Some(display_id) = display_id {
-            Ok(Self::from_display_id(
-                display_id as usize,
-                is_cursor_capture_enabled,
-                is_border_required,
-                cpu_access,
-            )?)
+            let display_id = usize::try_from(display_id).map_err(|_| {
+                PyValueError::new_err(format!(
+                    "display_id must be non-negative, got {}",
+                    display_id
+                ))
+            })?;
+            Ok(Self::from_display_id(display_id, &options)?)
+        } else if let Some(display_name) = display_name {
+            Ok(Self::from_display_name(display_name, &options)?)
+        } else if primary_display {
+            Ok(Self::from_primary_display(&options)?)
         } else {
             Err(Error::NeitherNameNorHandleIsSet)?
         }
@@ -183,23 +610,444 @@ impl Capture {
         Ok(self.inner.capturable().get_raw_handle())
     }
 
-    pub fn start(&self) -> PyResult<()> {
+    /// (width, height) of the captured frames.
+    #[getter]
+    pub fn size(&self) -> (u32, u32) {
+        self.inner.content_size()
+    }
+
+    /// (width, height) of the capture item (e.g. the whole window including its frame), known
+    /// before the first frame arrives.
+    #[getter]
+    pub fn initial_size(&self) -> (i32, i32) {
+        let size = self.inner.initial_size();
+        (size.Width, size.Height)
+    }
+
+    /// (left, top, right, bottom) of the region of the capture item copied into frames.
+    #[getter]
+    pub fn capture_box(&self) -> (u32, u32, u32, u32) {
+        let capture_box = self.inner.capture_box();
+        (
+            capture_box.left,
+            capture_box.top,
+            capture_box.right,
+            capture_box.bottom,
+        )
+    }
+
+    /// Raw `ID3D11Device` pointer used by this capture, for interop with other GPU libraries.
+    ///
+    /// The device is owned by the capture and is only valid while the capture is alive.
+    #[getter]
+    pub fn device_ptr(&self) -> usize {
+        self.inner.device().as_raw() as usize
+    }
+
+    /// Raw `ID3D11DeviceContext` pointer used by this capture, for interop with other GPU
+    /// libraries.
+    ///
+    /// The context is owned by the capture and is only valid while the capture is alive.
+    #[getter]
+    pub fn context_ptr(&self) -> usize {
+        self.inner.context().as_raw() as usize
+    }
+
+    #[getter]
+    pub fn is_stopped(&self) -> bool {
+        self.inner.is_stopped()
+    }
+
+    /// Whether disabling the capture border has failed (e.g. on older systems).
+    #[getter]
+    pub fn border_change_failed(&self) -> bool {
+        self.inner.border_change_failed()
+    }
+
+    /// Expected time between frames in seconds, based on the refresh rate of the display showing
+    /// the capture target. Can be used to size sleeps when polling with `wait_timeout`. `None` if
+    /// unknown.
+    #[getter]
+    pub fn frame_interval_hint(&self) -> Option<f64> {
+        self.inner
+            .frame_interval_hint()
+            .map(|interval| interval.as_secs_f64())
+    }
+
+    #[getter]
+    pub fn close_reason(&self) -> Option<String> {
+        self.inner
+            .close_reason()
+            .map(|reason| format!("{:?}", reason))
+    }
+
+    pub fn start(&mut self) -> PyResult<()> {
         Ok(self._start()?)
     }
 
+    /// Grab the next frame. Unless the capture was created with `auto_start=False`, it is
+    /// started first if needed - `start()` can still be called explicitly.
     pub fn grab(&mut self) -> PyResult<Option<Frame>> {
         Ok(self._grab()?)
     }
 
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Grab the next frame, see `grab`. Iteration stops when the capture ends.
+    fn __next__(&mut self) -> PyResult<Option<Frame>> {
+        Ok(self._grab()?)
+    }
+
+    /// Grab up to `n` frames as copies, stopping early if the capture ends.
+    pub fn grab_many(&mut self, n: usize) -> PyResult<Vec<Frame>> {
+        Ok(self._grab_many(n)?)
+    }
+
+    /// Start capturing if needed and wait up to `timeout` seconds for the capture target to
+    /// produce a frame, which is discarded. Returns whether a frame has arrived.
+    pub fn wait_first_frame(&mut self, timeout: f64) -> PyResult<bool> {
+        let timeout = duration_from_secs("timeout", timeout)?;
+        Ok(self._wait_first_frame(timeout)?)
+    }
+
+    /// Map a pixel position in frames to (x, y) screen coordinates. Returns `None` if the screen
+    /// position of the capture target is unknown.
+    pub fn frame_to_screen(&self, x: u32, y: u32) -> Option<(i32, i32)> {
+        self.inner.frame_to_screen(x, y)
+    }
+
+    /// Map a screen position to (x, y) frame coordinates. Returns `None` if the screen position of
+    /// the capture target is unknown.
+    pub fn screen_to_frame(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        self.inner.screen_to_frame(x, y)
+    }
+
+    /// Discard frames which haven't been grabbed yet. Returns the number of discarded frames.
+    pub fn drain(&mut self) -> usize {
+        self.inner.drain()
+    }
+
+    pub fn set_cursor_capture_enabled(&mut self, val: bool) -> PyResult<()> {
+        Ok(self._set_cursor_capture_enabled(val)?)
+    }
+
+    /// Returns whether the change has actually been applied. Raises instead if the capture was
+    /// created with `require_no_border=True`.
+    pub fn set_border_required(&mut self, val: bool) -> PyResult<bool> {
+        Ok(self._set_border_required(val)?)
+    }
+
+    /// Whether the cursor is currently captured, read back from the capture session.
+    #[getter]
+    pub fn is_cursor_capture_enabled(&self) -> PyResult<bool> {
+        Ok(self._is_cursor_capture_enabled()?)
+    }
+
+    /// Whether the capture border is currently drawn, read back from the capture session.
+    #[getter]
+    pub fn is_border_required(&self) -> bool {
+        self.inner.is_border_required()
+    }
+
     pub fn stop(&mut self) -> PyResult<()> {
         Ok(self._stop()?)
     }
 }
 
+/// Captures all displays, composited into frames covering the whole virtual desktop.
+#[pyclass(unsendable)]
+pub struct VirtualDesktopCapture {
+    inner: ::zbl::VirtualDesktopCapture,
+}
+
+impl VirtualDesktopCapture {
+    fn _new(options: &CaptureOptions) -> Result<Self> {
+        ::zbl::init()?;
+        let inner = ::zbl::VirtualDesktopCapture::new(|builder| {
+            builder
+                .set_is_cursor_capture_enabled(options.is_cursor_capture_enabled)
+                .set_is_border_required(options.is_border_required)
+                .set_max_fps(options.max_fps)
+                .set_frame_policy(::zbl::FramePolicy::LatestOnly)
+        })?;
+        Ok(Self { inner })
+    }
+
+    fn _grab(&mut self) -> Result<Option<Frame>> {
+        Ok(self
+            .inner
+            .grab()?
+            .map(|frame| Frame::from_owned(frame.clone())))
+    }
+
+    fn _next(&mut self) -> Result<Option<Frame>> {
+        for capture in self.inner.captures() {
+            if !capture.is_started() {
+                capture.start()?;
+            }
+        }
+        self._grab()
+    }
+}
+
+#[pymethods]
+impl VirtualDesktopCapture {
+    #[new]
+    #[pyo3(signature = (is_cursor_capture_enabled=false, is_border_required=true, max_fps=None))]
+    pub fn new(
+        is_cursor_capture_enabled: bool,
+        is_border_required: bool,
+        max_fps: Option<f32>,
+    ) -> PyResult<Self> {
+        check_max_fps(max_fps)?;
+        Ok(Self::_new(&CaptureOptions {
+            is_cursor_capture_enabled,
+            is_border_required,
+            allow_border_change_failure: true,
+            cpu_access: true,
+            max_fps,
+            latest_only: true,
+            wait_timeout: None,
+            capture_cursor_metadata: false,
+            min_update_interval: None,
+            auto_start: true,
+            pixel_format:
+                ::zbl::windows::Graphics::DirectX::DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            output_format: ::zbl::OutputFormat::Native,
+            client_crop: None,
+        })?)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Grab the next frame, starting the capture if needed.
+    fn __next__(&mut self) -> PyResult<Option<Frame>> {
+        Ok(self._next()?)
+    }
+
+    /// (width, height) of the virtual desktop.
+    #[getter]
+    pub fn size(&self) -> (u32, u32) {
+        self.inner.content_size()
+    }
+
+    #[getter]
+    pub fn is_stopped(&mut self) -> bool {
+        self.inner.captures().all(|capture| capture.is_stopped())
+    }
+
+    pub fn start(&mut self) -> PyResult<()> {
+        Ok(self.inner.start().map_err(Error::from)?)
+    }
+
+    pub fn grab(&mut self) -> PyResult<Option<Frame>> {
+        Ok(self._grab()?)
+    }
+
+    pub fn stop(&mut self) -> PyResult<()> {
+        Ok(self.inner.stop().map_err(Error::from)?)
+    }
+}
+
+/// Information about a display, see `list_displays`.
+#[pyclass(get_all)]
+pub struct DisplayInfo {
+    pub id: usize,
+    pub name: String,
+    pub friendly_name: Option<String>,
+    pub width: i32,
+    pub height: i32,
+    pub is_primary: bool,
+    /// Refresh rate in Hz, best-effort (virtual and remote displays might report arbitrary values).
+    pub refresh_rate: Option<u32>,
+    /// Effective (x, y) DPI, 96 corresponds to 100% scale.
+    pub dpi: (u32, u32),
+    /// Whether HDR is enabled, `None` if HDR information is not available (as for the fields below).
+    pub hdr_enabled: Option<bool>,
+    /// Luminance of SDR white in HDR mode, in nits.
+    pub sdr_white_level: Option<f32>,
+    /// Peak luminance in nits, if reported by the display.
+    pub max_luminance: Option<f32>,
+}
+
+/// List all displays. The `id` of a display can be passed as `display_id` to `Capture`.
+#[pyfunction]
+pub fn list_displays() -> PyResult<Vec<DisplayInfo>> {
+    Ok(_list_displays()?)
+}
+
+fn _list_displays() -> Result<Vec<DisplayInfo>> {
+    ::zbl::Display::enumerate()?
+        .into_iter()
+        .enumerate()
+        .map(|(id, display)| {
+            let (width, height) = display.get_virtual_size();
+            let hdr_info = display.hdr_info();
+            Ok(DisplayInfo {
+                id,
+                name: display.display_name.clone(),
+                friendly_name: display.get_friendly_name(),
+                width,
+                height,
+                is_primary: display.is_primary(),
+                refresh_rate: display.refresh_rate(),
+                dpi: display.dpi()?,
+                hdr_enabled: hdr_info.map(|info| info.hdr_enabled),
+                sdr_white_level: hdr_info.map(|info| info.sdr_white_level),
+                max_luminance: hdr_info.and_then(|info| info.max_luminance),
+            })
+        })
+        .collect()
+}
+
+/// Get the id of the display the window with the given handle is on. A window spanning several
+/// displays resolves to the one showing the largest part of it.
+#[pyfunction]
+pub fn display_for_window(handle: isize) -> PyResult<usize> {
+    Ok(_display_for_window(handle)?)
+}
+
+fn _display_for_window(handle: isize) -> Result<usize> {
+    let display = ::zbl::Display::from_window(&::zbl::Window::new(HWND(handle as *mut c_void)))?;
+    ::zbl::Display::enumerate()?
+        .iter()
+        .position(|d| d.handle == display.handle)
+        .ok_or(Error::WindowDisplayNotFoundError(handle))
+}
+
+/// Check which optional capture features are available on this system. Returns a dict with
+/// `is_supported`, `cursor_toggle`, `border_toggle`, `min_update_interval`, `dirty_regions` and
+/// `secondary_windows` keys.
+#[pyfunction]
+pub fn capabilities() -> HashMap<&'static str, bool> {
+    let capabilities = ::zbl::session_capabilities();
+    HashMap::from([
+        ("is_supported", capabilities.is_supported),
+        ("cursor_toggle", capabilities.cursor_toggle),
+        ("border_toggle", capabilities.border_toggle),
+        ("min_update_interval", capabilities.min_update_interval),
+        ("dirty_regions", capabilities.dirty_regions),
+        ("secondary_windows", capabilities.secondary_windows),
+    ])
+}
+
+/// Get the reason the window with the given handle can't be captured, or `None` if it can be.
+#[pyfunction]
+pub fn capturable_rejection(handle: isize) -> Option<String> {
+    ::zbl::Window::new(HWND(handle as *mut c_void))
+        .is_capturable_detailed()
+        .err()
+        .map(|rejection| rejection.to_string())
+}
+
+#[pyfunction]
+pub fn window_from_point(x: i32, y: i32) -> Option<isize> {
+    ::zbl::Window::from_point(x, y).map(|window| window.handle.0 as isize)
+}
+
+/// Get the (left, top, right, bottom) window rectangle of the window with the given handle, in
+/// screen coordinates.
+#[pyfunction]
+pub fn window_rect(handle: isize) -> PyResult<(i32, i32, i32, i32)> {
+    let rect = ::zbl::Window::new(HWND(handle as *mut c_void))
+        .window_rect()
+        .map_err(Error::from)?;
+    Ok((rect.left, rect.top, rect.right, rect.bottom))
+}
+
+/// Restore the window with the given handle if it is minimized.
+#[pyfunction]
+pub fn restore_window(handle: isize) -> PyResult<()> {
+    ::zbl::Window::new(HWND(handle as *mut c_void))
+        .restore()
+        .map_err(Error::from)?;
+    Ok(())
+}
+
+/// Bring the window with the given handle to the foreground. This can fail due to the foreground
+/// restrictions of Windows, e.g. while another application holds the foreground lock.
+#[pyfunction]
+pub fn set_foreground_window(handle: isize) -> PyResult<()> {
+    ::zbl::Window::new(HWND(handle as *mut c_void))
+        .set_foreground()
+        .map_err(Error::from)?;
+    Ok(())
+}
+
+/// Get the (width, height) of the client area of the window with the given handle.
+#[pyfunction]
+pub fn client_rect(handle: isize) -> PyResult<(i32, i32)> {
+    let rect = ::zbl::Window::new(HWND(handle as *mut c_void))
+        .client_rect()
+        .map_err(Error::from)?;
+    Ok((rect.right - rect.left, rect.bottom - rect.top))
+}
+
+/// Get the (x, y) screen position of the client area of the window with the given handle.
+#[pyfunction]
+pub fn client_origin_on_screen(handle: isize) -> PyResult<(i32, i32)> {
+    let point = ::zbl::Window::new(HWND(handle as *mut c_void))
+        .client_origin_on_screen()
+        .map_err(Error::from)?;
+    Ok((point.x, point.y))
+}
+
+/// Record a window with given name into an MP4 file for `duration` seconds, or until the window
+/// is closed.
+#[pyfunction]
+#[pyo3(signature = (window_name, path, duration, bitrate=8_000_000))]
+pub fn record_window(
+    py: Python<'_>,
+    window_name: &str,
+    path: std::path::PathBuf,
+    duration: f64,
+    bitrate: u32,
+) -> PyResult<()> {
+    Ok(py.allow_threads(|| _record_window(window_name, &path, duration, bitrate))?)
+}
+
+fn _record_window(
+    window_name: &str,
+    path: &std::path::Path,
+    duration: f64,
+    bitrate: u32,
+) -> Result<()> {
+    ::zbl::init()?;
+    let window = ::zbl::Window::find_first(window_name)?;
+    let capture = ::zbl::CaptureBuilder::new(Box::new(window)).build()?;
+    let mut recorder = ::zbl::Recorder::new(capture, path);
+    recorder.set_bitrate(bitrate)?;
+    recorder.start()?;
+    let started_at = std::time::Instant::now();
+    while started_at.elapsed().as_secs_f64() < duration {
+        if !recorder.record_next()? {
+            break;
+        }
+    }
+    Ok(recorder.stop()?)
+}
+
 #[pymodule]
 #[pyo3(name = "zbl")]
 fn zbl(_py: Python<'_>, module: &Bound<'_, PyModule>) -> PyResult<()> {
     module.add_class::<Frame>()?;
     module.add_class::<Capture>()?;
+    module.add_class::<VirtualDesktopCapture>()?;
+    module.add_class::<DisplayInfo>()?;
+    module.add_function(wrap_pyfunction!(list_displays, module)?)?;
+    module.add_function(wrap_pyfunction!(display_for_window, module)?)?;
+    module.add_function(wrap_pyfunction!(capabilities, module)?)?;
+    module.add_function(wrap_pyfunction!(capturable_rejection, module)?)?;
+    module.add_function(wrap_pyfunction!(window_from_point, module)?)?;
+    module.add_function(wrap_pyfunction!(window_rect, module)?)?;
+    module.add_function(wrap_pyfunction!(client_rect, module)?)?;
+    module.add_function(wrap_pyfunction!(restore_window, module)?)?;
+    module.add_function(wrap_pyfunction!(set_foreground_window, module)?)?;
+    module.add_function(wrap_pyfunction!(client_origin_on_screen, module)?)?;
+    module.add_function(wrap_pyfunction!(record_window, module)?)?;
     Ok(())
 }
@@ -1,26 +1,34 @@
 import numpy
 import ctypes as C
 
-from typing import Iterator, Optional
-
-
-from .zbl import Capture as _NativeCapture, Frame
-
-
-uint8_ptr = C.POINTER(C.c_uint8)
+from typing import Iterator, List, Optional, Tuple
+
+
+from .zbl import (
+    Capture as _NativeCapture,
+    VirtualDesktopCapture as _NativeVirtualDesktopCapture,
+    DisplayInfo,
+    Frame,
+    capabilities,
+    capturable_rejection,
+    client_origin_on_screen,
+    client_rect,
+    display_for_window,
+    list_displays,
+    record_window,
+    restore_window,
+    set_foreground_window,
+    window_from_point,
+    window_rect,
+)
 
 # set proces to be DPI-aware
 _ = C.windll.shcore.SetProcessDpiAwareness(2)
 
 
 def frame_to_numpy_array(frame: Frame) -> numpy.ndarray:
-    arr = numpy.ctypeslib.as_array(
-        C.cast(frame.ptr, uint8_ptr), shape=(frame.height, frame.row_pitch)
-    )
-    if frame.row_pitch == frame.width * 4:
-        return arr.reshape(frame.height, frame.width, 4)
-    else:
-        return arr[:, : frame.width * 4].reshape((frame.height, frame.width, 4))
+    # zero-copy view, `Frame` implements the buffer protocol
+    return numpy.asarray(frame)
 
 
 class Capture:
@@ -33,21 +41,130 @@ class Capture:
         is_cursor_capture_enabled: bool = False,
         is_border_required: bool = True,
         use_staging_texture: bool = True,
+        display_name: Optional[str] = None,
+        primary_display: bool = False,
+        process_id: Optional[int] = None,
+        window_class: Optional[str] = None,
+        max_fps: Optional[float] = None,
+        latest_only: bool = False,
+        pixel_format: str = "bgra8",
+        capture_all_displays: bool = False,
+        wait_timeout: Optional[float] = None,
+        capture_cursor_metadata: bool = False,
+        min_update_interval_ms: Optional[int] = None,
+        auto_start: bool = True,
+        output_format: str = "native",
+        crop: Optional[Tuple[int, int, int, int]] = None,
+        require_no_border: bool = False,
     ):
+        if capture_all_displays:
+            # composites all displays into a single BGRA frame; only `size`, `is_stopped` and
+            # frame iteration are supported
+            self._inner = _NativeVirtualDesktopCapture(
+                is_cursor_capture_enabled=is_cursor_capture_enabled,
+                is_border_required=is_border_required,
+                max_fps=max_fps,
+            )
+            return
         self._inner = _NativeCapture(
-            window_name, window_handle, display_id, is_cursor_capture_enabled, is_border_required, use_staging_texture
+            window_name=window_name,
+            window_handle=window_handle,
+            display_id=display_id,
+            is_cursor_capture_enabled=is_cursor_capture_enabled,
+            is_border_required=is_border_required,
+            cpu_access=use_staging_texture,
+            display_name=display_name,
+            primary_display=primary_display,
+            process_id=process_id,
+            window_class=window_class,
+            max_fps=max_fps,
+            latest_only=latest_only,
+            pixel_format=pixel_format,
+            wait_timeout=wait_timeout,
+            capture_cursor_metadata=capture_cursor_metadata,
+            min_update_interval_ms=min_update_interval_ms,
+            auto_start=auto_start,
+            output_format=output_format,
+            crop=crop,
+            require_no_border=require_no_border,
         )
 
     @property
     def handle(self) -> int:
         return self._inner.handle()
 
+    @property
+    def size(self) -> Tuple[int, int]:
+        return self._inner.size
+
+    @property
+    def initial_size(self) -> Tuple[int, int]:
+        return self._inner.initial_size
+
+    @property
+    def capture_box(self) -> Tuple[int, int, int, int]:
+        return self._inner.capture_box
+
+    @property
+    def device_ptr(self) -> int:
+        """Raw ID3D11Device pointer, for advanced GPU interop. Owned by the capture."""
+        return self._inner.device_ptr
+
+    @property
+    def context_ptr(self) -> int:
+        """Raw ID3D11DeviceContext pointer, for advanced GPU interop. Owned by the capture."""
+        return self._inner.context_ptr
+
+    @property
+    def is_stopped(self) -> bool:
+        return self._inner.is_stopped
+
+    @property
+    def border_change_failed(self) -> bool:
+        return self._inner.border_change_failed
+
+    @property
+    def frame_interval_hint(self) -> Optional[float]:
+        return self._inner.frame_interval_hint
+
+    @property
+    def close_reason(self) -> Optional[str]:
+        return self._inner.close_reason
+
+    def set_cursor_capture_enabled(self, enabled: bool):
+        self._inner.set_cursor_capture_enabled(enabled)
+
+    def set_border_required(self, required: bool) -> bool:
+        return self._inner.set_border_required(required)
+
+    @property
+    def is_cursor_capture_enabled(self) -> bool:
+        return self._inner.is_cursor_capture_enabled
+
+    @property
+    def is_border_required(self) -> bool:
+        return self._inner.is_border_required
+
+    def grab_many(self, n: int) -> List[Frame]:
+        return self._inner.grab_many(n)
+
+    def wait_first_frame(self, timeout: float) -> bool:
+        return self._inner.wait_first_frame(timeout)
+
+    def frame_to_screen(self, x: int, y: int) -> Optional[Tuple[int, int]]:
+        return self._inner.frame_to_screen(x, y)
+
+    def screen_to_frame(self, x: int, y: int) -> Optional[Tuple[int, int]]:
+        return self._inner.screen_to_frame(x, y)
+
+    def drain(self) -> int:
+        return self._inner.drain()
+
     def raw_frames(self) -> Iterator[Frame]:
-        while True:
-            next_frame = self._inner.grab()
-            if next_frame is None:
-                break
-            yield next_frame
+        return iter(self._inner)
+
+    def __iter__(self) -> Iterator[Frame]:
+        return self.raw_frames()
 
     def frames(self) -> Iterator[numpy.ndarray]:
         for frame in self.raw_frames():
@@ -61,6 +178,19 @@ class Capture:
         self._inner.stop()
 
 
+def snapshot(**kwargs) -> Frame:
+    """
+    Capture a single frame and stop capturing. Accepts the same arguments as `Capture`.
+
+    This blocks until a frame arrives, which never happens while a window is minimized.
+    """
+    with Capture(**kwargs) as cap:
+        frame = cap._inner.grab()
+        if frame is None:
+            raise RuntimeError("capture target has been closed before a frame was captured")
+        return frame.copy()
+
+
 def show(args):
     from time import perf_counter
     import cv2